@@ -0,0 +1,91 @@
+use crate::block::BlockId;
+use serde::{Deserialize, Serialize};
+
+/// Tracks which `BlockId` slots are in use with one bit per slot, so that
+/// freed slots get handed out again before `FileSys.blocks` grows.
+#[derive(Serialize, Deserialize, Clone, Default, Debug)]
+pub struct Allocator {
+    /// Bit `i % 64` of word `i / 64` is set iff slot `i` is in use.
+    bits: Vec<u64>,
+    /// Number of slots covered, used or not.
+    len: usize,
+    /// Number of used slots.
+    used: usize,
+}
+
+impl Allocator {
+    /// An allocator with the first `len` slots in use.
+    pub fn with_used(len: usize) -> Self {
+        let mut bits = vec![u64::MAX; len / 64];
+        if !len.is_multiple_of(64) {
+            bits.push((1 << (len % 64)) - 1);
+        }
+        Allocator { bits, len, used: len }
+    }
+    /// Rebuild an allocator from what `words` returned.
+    pub fn from_words(len: usize, mut bits: Vec<u64>) -> Self {
+        bits.resize(len.div_ceil(64), 0);
+        let used = bits.iter().map(|word| word.count_ones() as usize).sum();
+        Allocator { bits, len, used }
+    }
+    /// The number of slots covered and the bitmap words covering them.
+    pub fn words(&self) -> (usize, &[u64]) {
+        (self.len, &self.bits)
+    }
+    pub fn is_used(&self, id: BlockId) -> bool {
+        id.0 < self.len && self.bits[id.0 / 64] & (1 << (id.0 % 64)) != 0
+    }
+    /// Number of slots covered, used or not.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+    /// Number of free slots below `len`.
+    pub fn free_count(&self) -> usize {
+        self.len - self.used
+    }
+    /// Claim the lowest free slot, growing by one slot when there is none.
+    pub fn alloc(&mut self) -> BlockId {
+        let idx = match self.bits.iter().position(|&word| word != u64::MAX) {
+            Some(word) if word * 64 + (self.bits[word].trailing_ones() as usize) < self.len => {
+                word * 64 + self.bits[word].trailing_ones() as usize
+            }
+            _ => {
+                if self.len.is_multiple_of(64) {
+                    self.bits.push(0);
+                }
+                self.len += 1;
+                self.len - 1
+            }
+        };
+        self.bits[idx / 64] |= 1 << (idx % 64);
+        self.used += 1;
+        BlockId(idx)
+    }
+    /// Mark the free slot `id` used, growing to cover it if need be.
+    pub fn claim(&mut self, id: BlockId) {
+        assert!(!self.is_used(id), "double allocation of {:?}", id);
+        if id.0 >= self.len {
+            self.resize(id.0 + 1);
+        }
+        self.bits[id.0 / 64] |= 1 << (id.0 % 64);
+        self.used += 1;
+    }
+    /// Cover exactly `len` slots, the new ones free.
+    pub fn resize(&mut self, len: usize) {
+        for idx in len..self.len {
+            if self.is_used(BlockId(idx)) {
+                self.release(BlockId(idx));
+            }
+        }
+        self.bits.resize(len.div_ceil(64), 0);
+        self.len = len;
+    }
+    pub fn release(&mut self, id: BlockId) {
+        assert!(self.is_used(id), "double free of {:?}", id);
+        self.bits[id.0 / 64] &= !(1 << (id.0 % 64));
+        self.used -= 1;
+    }
+}
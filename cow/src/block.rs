@@ -1,35 +1,139 @@
 use crate::FileSys;
 use serde::{Deserialize, Serialize};
 use std::{
-    ops::{Index, IndexMut},
-    sync::{Arc, RwLock},
+    fmt::Display,
+    ops::Index,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        OnceLock, RwLock,
+    },
 };
 
-#[derive(Serialize, Deserialize, Clone, Copy, Hash, PartialEq, Eq)]
+#[derive(Serialize, Deserialize, Clone, Copy, Hash, PartialEq, Eq, PartialOrd, Ord, Debug)]
 pub struct BlockId(pub(crate) usize);
 
-impl Index<BlockId> for FileSys {
-    type Output = Arc<RwLock<Block>>;
+impl Display for BlockId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "#{}", self.0)
+    }
+}
+
+type Segment = Box<[RwLock<Block>]>;
+
+/// Slots in the first segment of a `BlockTable`; every further segment is
+/// twice the size of the one before.
+const FIRST_SEGMENT: usize = 64;
+const SEGMENTS: usize = 48;
+
+/// The blocks of a file system, each behind a lock of its own. The table
+/// grows a segment at a time and never moves a block, so that locking a
+/// block takes no lock on the table.
+pub struct BlockTable {
+    segments: Box<[OnceLock<Segment>]>,
+    len: AtomicUsize,
+}
+
+impl BlockTable {
+    /// The segment slot `idx` lives in and its offset there.
+    fn locate(idx: usize) -> (usize, usize) {
+        let segment = (idx / FIRST_SEGMENT + 1).ilog2() as usize;
+        (segment, idx - FIRST_SEGMENT * ((1 << segment) - 1))
+    }
+    pub fn len(&self) -> usize {
+        self.len.load(Ordering::Acquire)
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    pub fn get(&self, id: BlockId) -> Option<&RwLock<Block>> {
+        if id.0 >= self.len() {
+            return None;
+        }
+        let (segment, offset) = Self::locate(id.0);
+        Some(&self.segments[segment].get()?[offset])
+    }
+    /// Append `block`. Appends must not race each other; the file system
+    /// only appends with its bookkeeping locked.
+    pub(crate) fn push(&self, block: Block) -> BlockId {
+        let idx = self.len.load(Ordering::Acquire);
+        let (segment, offset) = Self::locate(idx);
+        let segment = self.segments[segment].get_or_init(|| {
+            (0..FIRST_SEGMENT << segment)
+                .map(|_| RwLock::new(Block::Free))
+                .collect()
+        });
+        *segment[offset].write().unwrap() = block;
+        self.len.store(idx + 1, Ordering::Release);
+        BlockId(idx)
+    }
+    pub fn iter(&self) -> impl Iterator<Item = &RwLock<Block>> {
+        (0..self.len()).map(|idx| &self[BlockId(idx)])
+    }
+}
+
+impl FromIterator<Block> for BlockTable {
+    fn from_iter<I: IntoIterator<Item = Block>>(blocks: I) -> Self {
+        let table = BlockTable {
+            segments: (0..SEGMENTS).map(|_| OnceLock::new()).collect(),
+            len: AtomicUsize::new(0),
+        };
+        for block in blocks {
+            table.push(block);
+        }
+        table
+    }
+}
+
+impl Index<BlockId> for BlockTable {
+    type Output = RwLock<Block>;
 
     fn index(&self, index: BlockId) -> &Self::Output {
-        &self.blocks[index.0]
+        self.get(index).expect("block out of range")
     }
 }
 
-impl IndexMut<BlockId> for FileSys {
-    fn index_mut(&mut self, index: BlockId) -> &mut Self::Output {
-        &mut self.blocks[index.0]
+impl Index<BlockId> for FileSys {
+    type Output = RwLock<Block>;
+
+    fn index(&self, index: BlockId) -> &Self::Output {
+        // blocks of the loaded image are read in on first use
+        self.read_in(index);
+        &self.blocks[index]
     }
 }
 
-#[derive(Serialize, Deserialize, Clone)]
+/// Number of slots in a single `Block::DirEntries`.
+pub const DIR_ENTRIES_PER_BLOCK: usize = 16;
+/// Bytes in every `Data` block of a file except the last, which holds the rest.
+pub const DATA_BLOCK_SIZE: usize = 4096;
+/// Children of a file `INode`. A file with more `Data` blocks than that keeps
+/// them below a tree of `Block::Indirect`.
+pub const DIRECT_BLOCKS: usize = 16;
+/// Children of a single `Block::Indirect`.
+pub const INDIRECT_BLOCKS: usize = 512;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub enum Block {
     INode(INode),
     DirEntries(Vec<Option<DirEntry>>),
     Data(Data),
+    /// The next level down the `Data` blocks of a large file, either `Data`
+    /// blocks or further `Indirect` blocks, all of the same height.
+    Indirect(Vec<BlockId>),
+    /// A reclaimed block, no longer referenced from anywhere.
+    Free,
 }
 
 impl Block {
+    /// Every block this block refers to, once per reference.
+    pub fn children(&self) -> Vec<BlockId> {
+        match self {
+            Block::INode(inode) => inode.children.clone(),
+            Block::DirEntries(entries) => entries.iter().flatten().map(|entry| entry.inode).collect(),
+            Block::Indirect(ids) => ids.clone(),
+            Block::Data(_) | Block::Free => vec![],
+        }
+    }
     pub fn inode(self) -> INode {
         match self {
             Block::INode(inode) => inode,
@@ -48,16 +152,24 @@ impl Block {
             _ => panic!("Not a Data"),
         }
     }
+    pub fn indirect(self) -> Vec<BlockId> {
+        match self {
+            Block::Indirect(ids) => ids,
+            _ => panic!("Not an Indirect"),
+        }
+    }
 }
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
 #[repr(u8)]
 pub enum BlockType {
     File,
     Dir,
+    /// an `INode` whose content is the target path
+    Symlink,
 }
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct DirEntry {
     pub name: String,
     // pub owner: String,
@@ -68,13 +180,43 @@ pub struct DirEntry {
     pub inode: BlockId,
 }
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct INode {
     pub ref_cnt: usize,
     pub children: Vec<BlockId>,
 }
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Hash, Debug)]
 pub struct Data {
     pub data: Vec<u8>,
 }
+
+impl Display for Data {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut data = String::new();
+        for byte in &self.data {
+            data += &format!("{:02x}", byte);
+        }
+        write!(f, "{}", data)
+    }
+}
+
+impl Data {
+    pub fn new(raw: impl AsRef<[u8]>) -> Self {
+        Self {
+            data: raw.as_ref().to_vec(),
+        }
+    }
+}
+
+impl From<Vec<u8>> for Data {
+    fn from(data: Vec<u8>) -> Self {
+        Self { data }
+    }
+}
+
+impl AsRef<[u8]> for Data {
+    fn as_ref(&self) -> &[u8] {
+        &self.data
+    }
+}
@@ -0,0 +1,80 @@
+use crate::{block::BlockId, FileSys};
+
+/// The branch a fresh `FileSys` starts on.
+pub const MAIN_BRANCH: &str = "main";
+
+pub(crate) fn main() -> String {
+    MAIN_BRANCH.to_owned()
+}
+
+/* ------------------------------ branch table ------------------------------ */
+
+impl FileSys {
+    /// Name of the checked-out branch, the one `IFileSystem` operations act on.
+    pub fn head(&self) -> &str {
+        &self.head
+    }
+    fn has_branch(&self, name: &str) -> bool {
+        self.head == name || self.branches.contains_key(name)
+    }
+    /// Fork a writable branch `name` off the snapshot `snapshot`. The branch
+    /// starts out sharing every block with the snapshot; it only gets blocks
+    /// of its own as it is modified.
+    pub fn branch(&mut self, snapshot: &str, name: impl Into<String>) -> anyhow::Result<BlockId> {
+        let name = name.into();
+        let root = *self
+            .snapshots
+            .get(snapshot)
+            .ok_or_else(|| anyhow::anyhow!("snapshot `{}` not found", snapshot))?;
+        if self.has_branch(&name) {
+            anyhow::bail!("branch `{}` already exists", name)
+        }
+        self.branches.insert(name, root);
+        self.retain(root);
+        Ok(root)
+    }
+    /// Make `name` the branch that `IFileSystem` operations act on.
+    pub fn checkout(&mut self, name: &str) -> anyhow::Result<()> {
+        if self.head == name {
+            return Ok(());
+        }
+        let root = self
+            .branches
+            .remove(name)
+            .ok_or_else(|| anyhow::anyhow!("branch `{}` not found", name))?;
+        let head = std::mem::replace(&mut self.head, name.to_owned());
+        let old_root = std::mem::replace(self.root.get_mut().unwrap(), root);
+        self.branches.insert(head, old_root);
+        Ok(())
+    }
+    /// Names of all branches, in order, with their current roots.
+    pub fn list_branches(&self) -> Vec<(String, BlockId)> {
+        let mut branches = self.branches.clone();
+        branches.insert(self.head.clone(), self.root());
+        branches.into_iter().collect()
+    }
+    /// The root recorded under `name`, looked up among snapshots first and
+    /// branches second.
+    pub fn named_root(&self, name: &str) -> Option<BlockId> {
+        if let Some(&root) = self.snapshots.get(name) {
+            return Some(root);
+        }
+        if self.head == name {
+            return Some(self.root());
+        }
+        self.branches.get(name).copied()
+    }
+    /// Drop the branch `name`, freeing every block only it still used; the
+    /// checked-out branch cannot be deleted.
+    pub fn delete_branch(&mut self, name: &str) -> anyhow::Result<()> {
+        if self.head == name {
+            anyhow::bail!("cannot delete the checked-out branch `{}`", name)
+        }
+        let root = self
+            .branches
+            .remove(name)
+            .ok_or_else(|| anyhow::anyhow!("branch `{}` not found", name))?;
+        self.release(root);
+        Ok(())
+    }
+}
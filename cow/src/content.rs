@@ -0,0 +1,279 @@
+use crate::{
+    block::{Block, BlockId, Data, DATA_BLOCK_SIZE, DIRECT_BLOCKS, INDIRECT_BLOCKS},
+    FileSys,
+};
+
+/* ------------------------------ file contents ----------------------------- */
+
+// The content of a file is cut into `DATA_BLOCK_SIZE` chunks, each in its own
+// `Data` block; only the last chunk may be shorter, and it is never empty.
+// The children of a file `INode` are either the `Data` blocks themselves, or,
+// once there are more than `DIRECT_BLOCKS` of them, `Indirect` blocks whose
+// children are one level closer to the `Data` blocks. All children of a node
+// have the same height, a `Data` block having height 0.
+//
+// The functions below take and return the children of a file `INode`. They
+// only shadow the `Data` blocks that change and the `Indirect` blocks above
+// them; everything else is shared with the old version of the file.
+
+/// Number of chunks below a node of height `height`.
+fn span(height: usize) -> usize {
+    INDIRECT_BLOCKS.pow(height as u32)
+}
+
+/// Number of chunks a file `INode` holds with children of height `height`.
+fn capacity(height: usize) -> usize {
+    DIRECT_BLOCKS * span(height)
+}
+
+impl FileSys {
+    pub fn indirect(&self, id: BlockId) -> Vec<BlockId> {
+        self[id].read().unwrap().clone().indirect()
+    }
+    /// Length in bytes of the `Data` block `id`, without copying it.
+    pub(crate) fn data_len(&self, id: BlockId) -> usize {
+        match &*self[id].read().unwrap() {
+            Block::Data(data) => data.data.len(),
+            _ => panic!("Not a Data"),
+        }
+    }
+    /// Height of the nodes in `ids`.
+    fn height(&self, ids: &[BlockId]) -> usize {
+        let mut height = 0;
+        let mut next = ids.first().copied();
+        while let Some(id) = next {
+            match &*self[id].read().unwrap() {
+                Block::Indirect(ids) => next = ids.first().copied(),
+                _ => break,
+            }
+            height += 1;
+        }
+        height
+    }
+    /// Number of chunks below `ids`, nodes of height `height`.
+    fn chunk_count(&self, ids: &[BlockId], height: usize) -> usize {
+        match ids.last() {
+            None => 0,
+            Some(_) if height == 0 => ids.len(),
+            Some(&last) => (ids.len() - 1) * span(height) + self.chunk_count(&self.indirect(last), height - 1),
+        }
+    }
+    /// The `Data` block of chunk `idx` below `ids`, nodes of height `height`.
+    fn chunk(&self, ids: &[BlockId], height: usize, idx: usize) -> BlockId {
+        let id = ids[idx / span(height)];
+        if height == 0 {
+            return id;
+        }
+        self.chunk(&self.indirect(id), height - 1, idx % span(height))
+    }
+    /// `ids` with the chunks from `first` on replaced by `chunks`, which may
+    /// reach past the last chunk.
+    fn splice(&self, mut ids: Vec<BlockId>, height: usize, first: usize, chunks: &[BlockId]) -> Vec<BlockId> {
+        let span = span(height);
+        let mut done = 0;
+        while done < chunks.len() {
+            let idx = first + done;
+            let (slot, within) = (idx / span, idx % span);
+            let take = (span - within).min(chunks.len() - done);
+            let id = if height == 0 {
+                chunks[done]
+            } else {
+                let below = match ids.get(slot) {
+                    Some(&id) => self.indirect(id),
+                    None => vec![],
+                };
+                let below = self.splice(below, height - 1, within, &chunks[done..done + take]);
+                self.fresh(Block::Indirect(below))
+            };
+            match ids.get_mut(slot) {
+                Some(old) => *old = id,
+                None => ids.push(id),
+            }
+            done += take;
+        }
+        ids
+    }
+    /// `ids` with only the first `count` chunks left.
+    fn cut(&self, mut ids: Vec<BlockId>, height: usize, count: usize) -> Vec<BlockId> {
+        let span = span(height);
+        ids.truncate(count.div_ceil(span));
+        let rest = count % span;
+        if height > 0 && rest > 0 {
+            let last = ids.len() - 1;
+            let below = self.indirect(ids[last]);
+            let below = self.cut(below, height - 1, rest);
+            ids[last] = self.fresh(Block::Indirect(below));
+        }
+        ids
+    }
+    /// `children`, nodes of height `height` that hold `count` chunks, without
+    /// the levels so few chunks do not need.
+    fn lower(&self, mut children: Vec<BlockId>, mut height: usize, count: usize) -> Vec<BlockId> {
+        while height > 0 && count <= capacity(height - 1) {
+            children = match children.first() {
+                Some(&id) => self.indirect(id),
+                None => vec![],
+            };
+            height -= 1;
+        }
+        children
+    }
+    /// Size in bytes of the content held by `children`.
+    pub(crate) fn content_len(&self, children: &[BlockId]) -> usize {
+        let height = self.height(children);
+        match self.chunk_count(children, height) {
+            0 => 0,
+            count => (count - 1) * DATA_BLOCK_SIZE + self.data_len(self.chunk(children, height, count - 1)),
+        }
+    }
+    /// Up to `len` bytes of the content held by `children`, from `offset` on.
+    pub(crate) fn read_range(&self, children: &[BlockId], offset: usize, len: usize) -> Vec<u8> {
+        let end = offset.saturating_add(len).min(self.content_len(children));
+        if offset >= end {
+            return vec![];
+        }
+        let height = self.height(children);
+        let mut out = Vec::with_capacity(end - offset);
+        for idx in offset / DATA_BLOCK_SIZE..=(end - 1) / DATA_BLOCK_SIZE {
+            let start = idx * DATA_BLOCK_SIZE;
+            let Block::Data(data) = &*self[self.chunk(children, height, idx)].read().unwrap() else {
+                panic!("Not a Data")
+            };
+            out.extend_from_slice(&data.data[offset.max(start) - start..end.min(start + data.data.len()) - start]);
+        }
+        out
+    }
+    /// `children` with chunks `first..=last` rewritten for a content of
+    /// `new_len` bytes: each keeps its old bytes, is filled up with zeros and
+    /// then has the part of `bytes`, placed at `offset`, that falls into it.
+    fn rewrite(
+        &self, children: Vec<BlockId>, new_len: usize, first: usize, last: usize, offset: usize, bytes: &[u8],
+    ) -> Vec<BlockId> {
+        let mut height = self.height(&children);
+        let count = self.chunk_count(&children, height);
+        let mut chunks = Vec::with_capacity(last + 1 - first);
+        for idx in first..=last {
+            let start = idx * DATA_BLOCK_SIZE;
+            let mut data = if idx < count {
+                self.data(self.chunk(&children, height, idx))
+            } else {
+                Data::from(vec![])
+            };
+            data.data.resize(DATA_BLOCK_SIZE.min(new_len - start), 0);
+            let (lo, hi) = (offset.max(start), (offset + bytes.len()).min(start + data.data.len()));
+            if lo < hi {
+                data.data[lo - start..hi - start].copy_from_slice(&bytes[lo - offset..hi - offset]);
+            }
+            chunks.push(self.fresh_data(data));
+        }
+        let mut children = children;
+        while last >= capacity(height) {
+            if !children.is_empty() {
+                children = vec![self.fresh(Block::Indirect(children))];
+            }
+            height += 1;
+        }
+        self.splice(children, height, first, &chunks)
+    }
+    /// `children` with `bytes` written at `offset`, zero-filling any gap
+    /// between the end of the content and `offset`. The end of the write must
+    /// not overflow, which `write_at_in` checks.
+    pub(crate) fn write_range(&self, children: Vec<BlockId>, offset: usize, bytes: &[u8]) -> Vec<BlockId> {
+        if bytes.is_empty() {
+            return children;
+        }
+        let len = self.content_len(&children);
+        let end = offset + bytes.len();
+        let first = offset.min(len) / DATA_BLOCK_SIZE;
+        self.rewrite(
+            children,
+            len.max(end),
+            first,
+            (end - 1) / DATA_BLOCK_SIZE,
+            offset,
+            bytes,
+        )
+    }
+    /// `children` cut down, or extended with zeros, to `new_len` bytes.
+    pub(crate) fn truncate_range(&self, children: Vec<BlockId>, new_len: usize) -> Vec<BlockId> {
+        let len = self.content_len(&children);
+        if new_len > len {
+            let (first, last) = (len / DATA_BLOCK_SIZE, (new_len - 1) / DATA_BLOCK_SIZE);
+            return self.rewrite(children, new_len, first, last, 0, &[]);
+        }
+        if new_len == len {
+            return children;
+        }
+        let height = self.height(&children);
+        let count = new_len.div_ceil(DATA_BLOCK_SIZE);
+        let mut children = self.cut(children, height, count);
+        if !new_len.is_multiple_of(DATA_BLOCK_SIZE) {
+            let mut data = self.data(self.chunk(&children, height, count - 1));
+            data.data.truncate(new_len % DATA_BLOCK_SIZE);
+            let chunk = self.fresh_data(data);
+            children = self.splice(children, height, count - 1, &[chunk]);
+        }
+        self.lower(children, height, count)
+    }
+    /// Cut the content of each file `INode` in `cuts` down to its first
+    /// `count` chunks, which must be all it holds in front of any chunk or
+    /// `Indirect` block that was dropped from it. The `INode`s are rewritten
+    /// in place, as by `rechunk`, and the reference counts are left for the
+    /// caller to rebuild.
+    pub(crate) fn cut_files(&mut self, cuts: &[(BlockId, usize)]) {
+        for &(id, count) in cuts {
+            let Block::INode(inode) = self[id].read().unwrap().clone() else {
+                continue;
+            };
+            let height = self.height(&inode.children);
+            let children = self.cut(inode.children, height, count);
+            let children = self.lower(children, height, count);
+            *self[id].write().unwrap() = Block::INode(crate::block::INode { children, ..inode });
+        }
+        self.books.get_mut().unwrap().pending.clear();
+    }
+    /// Split the content of every file still kept the way legacy JSON images
+    /// did, in `Data` blocks of any size, into chunks. The `INode`s are
+    /// rewritten in place so that trees sharing them keep sharing them; the
+    /// reference counts are left for the caller to rebuild. Returns whether
+    /// anything changed.
+    pub(crate) fn rechunk(&mut self) -> bool {
+        let mut changed = false;
+        for idx in 0..self.blocks.len() {
+            let id = BlockId(idx);
+            let Block::INode(inode) = self[id].read().unwrap().clone() else {
+                continue;
+            };
+            // only the `INode` of a file that has nothing but `Data` children
+            let lens = inode.children.iter().map(|&child| match &*self[child].read().unwrap() {
+                Block::Data(data) => Some(data.data.len()),
+                _ => None,
+            });
+            let Some(lens) = lens.collect::<Option<Vec<_>>>() else {
+                continue;
+            };
+            let chunked = match lens.split_last() {
+                None => true,
+                Some((last, init)) => {
+                    lens.len() <= DIRECT_BLOCKS
+                        && (1..=DATA_BLOCK_SIZE).contains(last)
+                        && init.iter().all(|&len| len == DATA_BLOCK_SIZE)
+                }
+            };
+            if chunked {
+                continue;
+            }
+            let mut bytes = Vec::new();
+            for child in &inode.children {
+                bytes.extend(self.data(*child).data);
+            }
+            let children = self.write_range(vec![], 0, &bytes);
+            *self[id].write().unwrap() = Block::INode(crate::block::INode { children, ..inode });
+            changed = true;
+        }
+        // the new blocks are linked into their trees in place, not by a root
+        // swap, so they are not to be freed as never linked
+        self.books.get_mut().unwrap().pending.clear();
+        changed
+    }
+}
@@ -0,0 +1,120 @@
+use crate::{
+    block::{Block, BlockId, Data},
+    disk::{self, Kind},
+    FileSys,
+};
+use std::collections::HashMap;
+
+/// Finds the `Data` block already holding some content, so that identical
+/// chunks anywhere in the file system share a single block.
+///
+/// Only the checksum of the content is kept, the one the block map of an
+/// image holds too, so that blocks not read in yet are found all the same; a
+/// candidate is compared byte for byte before it is reused, so a collision
+/// costs a block, never correctness. The index is not stored in the image
+/// but rebuilt from the blocks.
+#[derive(Clone, Default, Debug)]
+pub struct DedupIndex {
+    by_hash: HashMap<u32, BlockId>,
+}
+
+fn hash(data: &[u8]) -> u32 {
+    disk::checksum(Kind::Data, data)
+}
+
+/// How much the sharing of `Data` blocks saves, over all trees.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct SpaceReport {
+    /// Live `Data` blocks.
+    pub data_blocks: usize,
+    /// Bytes held by the live `Data` blocks.
+    pub stored: u64,
+    /// Bytes the same references would take with a block of their own each.
+    pub referenced: u64,
+}
+
+impl SpaceReport {
+    pub fn saved(&self) -> u64 {
+        self.referenced - self.stored
+    }
+}
+
+impl DedupIndex {
+    /// Forget the `Data` block `id`, which is being freed.
+    pub(crate) fn forget(&mut self, id: BlockId, data: &Data) {
+        let key = hash(&data.data);
+        if self.by_hash.get(&key) == Some(&id) {
+            self.by_hash.remove(&key);
+        }
+    }
+}
+
+impl FileSys {
+    pub fn dedup_enabled(&self) -> bool {
+        self.books().dedup.is_some()
+    }
+    /// Turn deduplication on or off. Turning it on indexes the `Data` blocks
+    /// already present; duplicates among them stay separate blocks.
+    pub fn set_dedup(&mut self, on: bool) {
+        let index = on.then(|| self.build_dedup());
+        self.books.get_mut().unwrap().dedup = index;
+    }
+    pub(crate) fn build_dedup(&self) -> DedupIndex {
+        let mut index = DedupIndex::default();
+        for idx in 0..self.blocks.len() {
+            let id = BlockId(idx);
+            let key = match self.unread.entry(id) {
+                Some(entry) => (entry.kind == Kind::Data).then_some(entry.sum),
+                None => match &*self.blocks[id].read().unwrap() {
+                    Block::Data(data) => Some(hash(&data.data)),
+                    _ => None,
+                },
+            };
+            if let Some(key) = key {
+                index.by_hash.entry(key).or_insert(id);
+            }
+        }
+        index
+    }
+    /// A `Data` block holding `data`: an existing one when deduplication is on
+    /// and finds one, a fresh one otherwise.
+    pub(crate) fn fresh_data(&self, data: Data) -> BlockId {
+        let key = hash(&data.data);
+        let found = match &self.books().dedup {
+            Some(index) => index.by_hash.get(&key).copied(),
+            None => None,
+        };
+        if let Some(id) = found {
+            if matches!(&*self[id].read().unwrap(), Block::Data(found) if found.data == data.data) {
+                return id;
+            }
+        }
+        let id = self.fresh(Block::Data(data));
+        if let Some(index) = &mut self.books().dedup {
+            // replaces an entry gone stale or, rarely, a collision
+            index.by_hash.insert(key, id);
+        }
+        id
+    }
+    /// Count what the sharing of `Data` blocks saves.
+    pub fn space_report(&self) -> SpaceReport {
+        let mut report = SpaceReport::default();
+        let books = self.books();
+        for idx in 0..self.blocks.len() {
+            let id = BlockId(idx);
+            let len = match self.unread.entry(id) {
+                Some(entry) => (entry.kind == Kind::Data).then_some(entry.size as u64),
+                None => match &*self.blocks[id].read().unwrap() {
+                    Block::Data(data) => Some(data.data.len() as u64),
+                    _ => None,
+                },
+            };
+            if let (Some(len), refs @ 1..) = (len, books.refs[idx]) {
+                report.data_blocks += 1;
+                report.stored += len;
+                report.referenced += len * refs as u64;
+            }
+        }
+        report
+    }
+}
@@ -0,0 +1,100 @@
+use crate::{
+    block::{BlockId, BlockType, DirEntry},
+    view::FPath,
+    FileSys,
+};
+use std::{collections::BTreeMap, fmt::Display};
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Change {
+    Added(FPath),
+    Removed(FPath),
+    Modified(FPath),
+    /// A file became a directory or the other way around.
+    TypeChanged(FPath),
+}
+
+impl Display for Change {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Change::Added(path) => write!(f, "+ {}", path),
+            Change::Removed(path) => write!(f, "- {}", path),
+            Change::Modified(path) => write!(f, "M {}", path),
+            Change::TypeChanged(path) => write!(f, "T {}", path),
+        }
+    }
+}
+
+impl FileSys {
+    /// Every path that differs between the trees under `old` and `new`, in
+    /// path order. Subtrees whose `INode` is the same block on both sides are
+    /// shared and hence skipped without being read.
+    pub fn diff(&self, old: BlockId, new: BlockId) -> Vec<Change> {
+        let mut changes = Vec::new();
+        self.diff_dir(FPath::root(), old, new, &mut changes);
+        changes.sort_by(|a, b| a.path().0.cmp(&b.path().0));
+        changes
+    }
+    fn diff_dir(&self, path: FPath, old: BlockId, new: BlockId, changes: &mut Vec<Change>) {
+        if old == new {
+            return;
+        }
+        let by_name = |dir| {
+            self.entries(dir)
+                .into_iter()
+                .map(|entry| (entry.name.clone(), entry))
+                .collect::<BTreeMap<_, _>>()
+        };
+        let mut old = by_name(old);
+        let new = by_name(new);
+        for (name, new) in new {
+            let path = path.join(&name);
+            match old.remove(&name) {
+                None => self.diff_whole(path, &new, &mut |path| changes.push(Change::Added(path))),
+                Some(old) if old.btype != new.btype => changes.push(Change::TypeChanged(path)),
+                Some(old) if old.inode == new.inode => {}
+                Some(old) => match new.btype {
+                    BlockType::Dir => self.diff_dir(path, old.inode, new.inode, changes),
+                    BlockType::File | BlockType::Symlink => {
+                        if !self.same_content(old.inode, new.inode) {
+                            changes.push(Change::Modified(path))
+                        }
+                    }
+                },
+            }
+        }
+        for (name, old) in old {
+            let path = path.join(&name);
+            self.diff_whole(path, &old, &mut |path| changes.push(Change::Removed(path)));
+        }
+    }
+    /// Report `entry` and, for a directory, everything below it.
+    fn diff_whole(&self, path: FPath, entry: &DirEntry, report: &mut dyn FnMut(FPath)) {
+        report(path.clone());
+        if entry.btype == BlockType::Dir {
+            for child in self.entries(entry.inode) {
+                let path = path.join(&child.name);
+                self.diff_whole(path, &child, report);
+            }
+        }
+    }
+    /// Whether two file `INode`s hold the same bytes. Two versions of a file
+    /// that only differ in their link count share all of their `Data` blocks.
+    pub(crate) fn same_content(&self, old: BlockId, new: BlockId) -> bool {
+        let old = self.inode(old).children;
+        let new = self.inode(new).children;
+        if old == new {
+            return true;
+        }
+        self.content_len(&old) == self.content_len(&new)
+            && self.read_range(&old, 0, usize::MAX) == self.read_range(&new, 0, usize::MAX)
+    }
+}
+
+impl Change {
+    pub fn path(&self) -> &FPath {
+        match self {
+            Change::Added(path) | Change::Removed(path) | Change::Modified(path) | Change::TypeChanged(path) => path,
+        }
+    }
+}
@@ -0,0 +1,811 @@
+//! The binary image format.
+//!
+//! An image is a sequence of `BLOCK_SIZE` slots, all integers little-endian:
+//!
+//! | slot(s)        | content                                                  |
+//! | -------------- | -------------------------------------------------------- |
+//! | 0, 1           | superblocks                                              |
+//! | 2..map_start   | block records, each from the start of a slot on          |
+//! | map_start..    | block map, one `MAP_ENTRY_SIZE` entry per `BlockId`      |
+//! | meta_start..   | tables: snapshots, branches, the allocator and the       |
+//! |                | reference counts                                         |
+//!
+//! A record takes as many whole slots as it needs. Slots are small, so that
+//! a compressed record takes fewer of them than the `DATA_BLOCK_SIZE` bytes
+//! of a full `Data` block do raw.
+//!
+//! Images of the JSON format that cowffs wrote before are still read, see
+//! `FileSys::fs_disk_load`; the first commit replaces them by an image of
+//! this format.
+//!
+//! Each block map entry stores the first slot of a record, its length as
+//! stored and decompressed, its `Compression` and the checksum of the block.
+//! Any single block can thus be read without touching the others, which is
+//! how `FileSys::fs_disk_load` reads them, and records written with different
+//! defaults can sit side by side.
+//!
+//! Every reference to a block, in a record, in the tables or in the
+//! superblock, is followed by the `checksum` of that block. As the
+//! checksum of a block covers the checksums of its children, the image forms
+//! a Merkle tree: a root checksum vouches for the whole tree below it.
+//!
+//! An image is changed by commits that never overwrite what the current
+//! superblock refers to. A commit appends the records of the
+//! blocks that changed, a new block map and new tables to the file, syncs
+//! them, and only then writes a superblock of the next generation into the
+//! slot the current one does not occupy. Loading takes the valid superblock
+//! of the highest generation, and as a superblock torn by a crash fails its
+//! own checksum, a crash at any point leaves either the old tree or the new
+//! one. A block map may point at records of earlier commits; once most of
+//! the file is garbage, the next commit rewrites it from scratch instead.
+
+use crate::{
+    alloc::Allocator,
+    block::{Block, BlockId, BlockType, Data, DirEntry, INode},
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    collections::BTreeSet,
+    fs::{File, OpenOptions},
+    io::{Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+pub const MAGIC: [u8; 8] = *b"COWFFS\0\0";
+pub const VERSION: u32 = 1;
+pub const BLOCK_SIZE: usize = 512;
+pub const MAP_ENTRY_SIZE: usize = 24;
+/// Identical `Data` blocks are shared.
+pub const FEATURE_DEDUP: u32 = 1;
+
+#[repr(u8)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Kind {
+    INode = 0,
+    DirEntries = 1,
+    Data = 2,
+    Free = 3,
+    Indirect = 4,
+}
+
+impl Kind {
+    pub fn of(block: &Block) -> Self {
+        match block {
+            Block::INode(_) => Kind::INode,
+            Block::DirEntries(_) => Kind::DirEntries,
+            Block::Data(_) => Kind::Data,
+            Block::Free => Kind::Free,
+            Block::Indirect(_) => Kind::Indirect,
+        }
+    }
+    fn decode(byte: u8) -> anyhow::Result<Self> {
+        Ok(match byte {
+            0 => Kind::INode,
+            1 => Kind::DirEntries,
+            2 => Kind::Data,
+            3 => Kind::Free,
+            4 => Kind::Indirect,
+            _ => anyhow::bail!("unknown block kind {}", byte),
+        })
+    }
+}
+
+/// Checksum of a block, over its kind and its uncompressed record.
+pub fn checksum(kind: Kind, record: &[u8]) -> u32 {
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(&[kind as u8]);
+    hasher.update(record);
+    hasher.finalize()
+}
+
+/* ------------------------------- compression ------------------------------ */
+
+/// How a block record is compressed. Chosen per image, recorded per block.
+#[repr(u8)]
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    #[default]
+    Raw = 0,
+    Lz4 = 1,
+    Deflate = 2,
+}
+
+impl Compression {
+    fn decode(byte: u8) -> anyhow::Result<Self> {
+        Ok(match byte {
+            0 => Compression::Raw,
+            1 => Compression::Lz4,
+            2 => Compression::Deflate,
+            _ => anyhow::bail!("unknown compression {}", byte),
+        })
+    }
+    /// Compress `record`, falling back to `Raw` when that does not make it
+    /// any smaller. Returns the compression actually used.
+    pub fn compress(self, record: Vec<u8>) -> (Self, Vec<u8>) {
+        let packed = match self {
+            Compression::Raw => return (Compression::Raw, record),
+            Compression::Lz4 => lz4_flex::compress_prepend_size(&record),
+            Compression::Deflate => miniz_oxide::deflate::compress_to_vec(&record, 6),
+        };
+        if packed.len() < record.len() {
+            (self, packed)
+        } else {
+            (Compression::Raw, record)
+        }
+    }
+    pub fn decompress(self, record: Vec<u8>) -> anyhow::Result<Vec<u8>> {
+        Ok(match self {
+            Compression::Raw => record,
+            Compression::Lz4 => lz4_flex::decompress_size_prepended(&record)?,
+            Compression::Deflate => miniz_oxide::inflate::decompress_to_vec(&record)
+                .map_err(|err| anyhow::anyhow!("corrupt deflate record: {}", err))?,
+        })
+    }
+}
+
+impl std::str::FromStr for Compression {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "raw" | "none" => Compression::Raw,
+            "lz4" => Compression::Lz4,
+            "deflate" => Compression::Deflate,
+            _ => anyhow::bail!("unknown compression `{}`, expected raw, lz4 or deflate", s),
+        })
+    }
+}
+
+/* ------------------------------- superblock ------------------------------- */
+
+#[derive(Clone, Debug)]
+pub struct SuperBlock {
+    pub version: u32,
+    pub block_size: u32,
+    pub block_count: u64,
+    pub root: BlockId,
+    pub map_start: u64,
+    pub meta_start: u64,
+    pub meta_len: u64,
+    pub features: u32,
+    /// What new records are compressed with.
+    pub compression: Compression,
+    /// Checksum of `root`.
+    pub root_sum: u32,
+    /// Number of the commit that wrote the superblock. Of the two, the one
+    /// with the higher generation is current.
+    pub generation: u64,
+}
+
+impl SuperBlock {
+    fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(BLOCK_SIZE);
+        buf.extend(MAGIC);
+        buf.extend(self.version.to_le_bytes());
+        buf.extend(self.block_size.to_le_bytes());
+        buf.extend(self.block_count.to_le_bytes());
+        buf.extend((self.root.0 as u64).to_le_bytes());
+        buf.extend(self.map_start.to_le_bytes());
+        buf.extend(self.meta_start.to_le_bytes());
+        buf.extend(self.meta_len.to_le_bytes());
+        buf.extend(self.features.to_le_bytes());
+        buf.push(self.compression as u8);
+        buf.extend(self.root_sum.to_le_bytes());
+        buf.extend(self.generation.to_le_bytes());
+        buf.extend(crc32fast::hash(&buf).to_le_bytes());
+        buf.resize(BLOCK_SIZE, 0);
+        buf
+    }
+    fn decode(buf: &[u8]) -> anyhow::Result<Self> {
+        let mut r = Cursor(buf);
+        if r.bytes(MAGIC.len())? != MAGIC {
+            anyhow::bail!("not a cowffs image")
+        }
+        let version = r.u32()?;
+        if version != VERSION {
+            anyhow::bail!("unsupported image version {}", version)
+        }
+        let block_size = r.u32()?;
+        if block_size as usize != BLOCK_SIZE {
+            anyhow::bail!("unsupported block size {}", block_size)
+        }
+        let superblock = SuperBlock {
+            version,
+            block_size,
+            block_count: r.u64()?,
+            root: BlockId(r.u64()? as usize),
+            map_start: r.u64()?,
+            meta_start: r.u64()?,
+            meta_len: r.u64()?,
+            features: r.u32()?,
+            compression: Compression::decode(r.u8()?)?,
+            root_sum: r.u32()?,
+            generation: r.u64()?,
+        };
+        let len = buf.len() - r.0.len();
+        if r.u32()? != crc32fast::hash(&buf[..len]) {
+            anyhow::bail!("torn superblock")
+        }
+        Ok(superblock)
+    }
+}
+
+/* -------------------------------- encoding -------------------------------- */
+
+/// Little-endian reader over a byte slice that fails instead of panicking.
+pub(crate) struct Cursor<'a>(pub &'a [u8]);
+
+impl<'a> Cursor<'a> {
+    pub fn bytes(&mut self, n: usize) -> anyhow::Result<&'a [u8]> {
+        if self.0.len() < n {
+            anyhow::bail!("truncated record")
+        }
+        let (head, tail) = self.0.split_at(n);
+        self.0 = tail;
+        Ok(head)
+    }
+    pub fn u8(&mut self) -> anyhow::Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+    pub fn u32(&mut self) -> anyhow::Result<u32> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into()?))
+    }
+    pub fn u64(&mut self) -> anyhow::Result<u64> {
+        Ok(u64::from_le_bytes(self.bytes(8)?.try_into()?))
+    }
+    pub fn string(&mut self) -> anyhow::Result<String> {
+        let len = self.u32()? as usize;
+        Ok(String::from_utf8(self.bytes(len)?.to_vec())?)
+    }
+}
+
+fn put_string(buf: &mut Vec<u8>, s: &str) {
+    buf.extend((s.len() as u32).to_le_bytes());
+    buf.extend(s.as_bytes());
+}
+
+/// The record of `block`, each reference followed by the checksum `sums`
+/// holds for it.
+pub fn encode_block(block: &Block, sums: &[u32]) -> Vec<u8> {
+    let mut buf = Vec::new();
+    let put_ref = |buf: &mut Vec<u8>, id: BlockId| {
+        buf.extend((id.0 as u64).to_le_bytes());
+        buf.extend(sums[id.0].to_le_bytes());
+    };
+    match block {
+        Block::INode(inode) => {
+            buf.extend((inode.ref_cnt as u64).to_le_bytes());
+            buf.extend((inode.children.len() as u32).to_le_bytes());
+            for &child in &inode.children {
+                put_ref(&mut buf, child);
+            }
+        }
+        Block::DirEntries(entries) => {
+            buf.extend((entries.len() as u32).to_le_bytes());
+            for entry in entries {
+                match entry {
+                    None => buf.push(0),
+                    Some(entry) => {
+                        buf.push(1);
+                        put_string(&mut buf, &entry.name);
+                        buf.push(entry.btype as u8);
+                        put_ref(&mut buf, entry.inode);
+                    }
+                }
+            }
+        }
+        Block::Data(data) => buf.extend(&data.data),
+        Block::Indirect(ids) => {
+            buf.extend((ids.len() as u32).to_le_bytes());
+            for &id in ids {
+                put_ref(&mut buf, id);
+            }
+        }
+        Block::Free => {}
+    }
+    buf
+}
+
+/// Decode a record of `kind`. Returns the block along with the checksums
+/// its references carry, in the order of `Block::children`.
+pub fn decode_block(kind: Kind, buf: &[u8]) -> anyhow::Result<(Block, Vec<u32>)> {
+    let mut r = Cursor(buf);
+    let mut sums = Vec::new();
+    let mut get_ref = |r: &mut Cursor| -> anyhow::Result<BlockId> {
+        let id = BlockId(r.u64()? as usize);
+        sums.push(r.u32()?);
+        Ok(id)
+    };
+    let block = match kind {
+        Kind::INode => {
+            let ref_cnt = r.u64()? as usize;
+            let len = r.u32()?;
+            let children = (0..len).map(|_| get_ref(&mut r)).collect::<anyhow::Result<_>>()?;
+            Block::INode(INode { ref_cnt, children })
+        }
+        Kind::DirEntries => {
+            let len = r.u32()?;
+            let mut entries = Vec::with_capacity(len as usize);
+            for _ in 0..len {
+                let entry = match r.u8()? {
+                    0 => None,
+                    _ => {
+                        let name = r.string()?;
+                        let btype = match r.u8()? {
+                            0 => BlockType::File,
+                            1 => BlockType::Dir,
+                            2 => BlockType::Symlink,
+                            byte => anyhow::bail!("unknown entry type {}", byte),
+                        };
+                        let inode = get_ref(&mut r)?;
+                        Some(DirEntry { name, btype, inode })
+                    }
+                };
+                entries.push(entry);
+            }
+            Block::DirEntries(entries)
+        }
+        Kind::Data => Block::Data(Data { data: buf.to_vec() }),
+        Kind::Free => Block::Free,
+        Kind::Indirect => {
+            let len = r.u32()?;
+            let ids = (0..len).map(|_| get_ref(&mut r)).collect::<anyhow::Result<_>>()?;
+            Block::Indirect(ids)
+        }
+    };
+    Ok((block, sums))
+}
+
+/* ---------------------------------- image --------------------------------- */
+
+/// Everything an image holds besides the blocks.
+#[derive(Clone, Debug)]
+pub struct Tables {
+    pub root: BlockId,
+    pub snapshots: BTreeMap<String, BlockId>,
+    pub head: String,
+    pub branches: BTreeMap<String, BlockId>,
+    pub alloc: Allocator,
+    /// Stored as `FEATURE_DEDUP`.
+    pub dedup: bool,
+    pub compression: Compression,
+    /// Checksum of each root, as read from an image. Not needed for writing
+    /// one, where the checksums are computed from the blocks.
+    pub root_sums: BTreeMap<BlockId, u32>,
+    /// Number of references to each block, from other blocks and from the
+    /// roots above. Empty for tables that go with blocks in memory, whose
+    /// references are counted instead.
+    pub refs: Vec<usize>,
+}
+
+fn slots(len: usize) -> usize {
+    len.div_ceil(BLOCK_SIZE)
+}
+
+fn encode_tables(tables: &Tables, sums: &[u32]) -> Vec<u8> {
+    let mut buf = Vec::new();
+    for table in [&tables.snapshots, &tables.branches] {
+        buf.extend((table.len() as u32).to_le_bytes());
+        for (name, root) in table {
+            put_string(&mut buf, name);
+            buf.extend((root.0 as u64).to_le_bytes());
+            buf.extend(sums[root.0].to_le_bytes());
+        }
+    }
+    put_string(&mut buf, &tables.head);
+    let (len, words) = tables.alloc.words();
+    buf.extend((len as u64).to_le_bytes());
+    for word in words {
+        buf.extend(word.to_le_bytes());
+    }
+    buf.extend((tables.refs.len() as u64).to_le_bytes());
+    for &refs in &tables.refs {
+        buf.extend((refs as u64).to_le_bytes());
+    }
+    buf
+}
+
+fn decode_tables(superblock: &SuperBlock, buf: &[u8]) -> anyhow::Result<Tables> {
+    let mut r = Cursor(buf);
+    let mut root_sums = BTreeMap::from([(superblock.root, superblock.root_sum)]);
+    let mut table = || -> anyhow::Result<BTreeMap<String, BlockId>> {
+        let len = r.u32()?;
+        (0..len)
+            .map(|_| {
+                let (name, root) = (r.string()?, BlockId(r.u64()? as usize));
+                root_sums.insert(root, r.u32()?);
+                Ok((name, root))
+            })
+            .collect()
+    };
+    let snapshots = table()?;
+    let branches = table()?;
+    let head = r.string()?;
+    let len = r.u64()? as usize;
+    let words = (0..len.div_ceil(64)).map(|_| r.u64()).collect::<anyhow::Result<_>>()?;
+    let refs = (0..r.u64()?)
+        .map(|_| Ok(r.u64()? as usize))
+        .collect::<anyhow::Result<_>>()?;
+    Ok(Tables {
+        root: superblock.root,
+        snapshots,
+        head,
+        branches,
+        alloc: Allocator::from_words(len, words),
+        dedup: superblock.features & FEATURE_DEDUP != 0,
+        compression: superblock.compression,
+        root_sums,
+        refs,
+    })
+}
+
+/// Where the record of a block sits in an image, as its block map entry says.
+#[derive(Clone, Copy, Debug)]
+pub struct MapEntry {
+    /// Slot the record starts at.
+    pub slot: u64,
+    /// Length of the record as stored.
+    pub len: u32,
+    /// Length of the record once decompressed.
+    pub size: u32,
+    /// Checksum of the block, the one references to it carry.
+    pub sum: u32,
+    pub kind: Kind,
+    pub compression: Compression,
+}
+
+impl MapEntry {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.extend(self.slot.to_le_bytes());
+        buf.extend(self.len.to_le_bytes());
+        buf.extend(self.size.to_le_bytes());
+        buf.extend(self.sum.to_le_bytes());
+        buf.push(self.kind as u8);
+        buf.push(self.compression as u8);
+        buf.extend([0; 2]);
+    }
+    fn decode(buf: &[u8]) -> anyhow::Result<Self> {
+        let mut r = Cursor(buf);
+        Ok(MapEntry {
+            slot: r.u64()?,
+            len: r.u32()?,
+            size: r.u32()?,
+            sum: r.u32()?,
+            kind: Kind::decode(r.u8()?)?,
+            compression: Compression::decode(r.u8()?)?,
+        })
+    }
+}
+
+/// What the current superblock of an image file refers to, which the next
+/// commit builds on.
+#[derive(Clone, Debug)]
+pub struct Committed {
+    pub generation: u64,
+    pub map: Vec<MapEntry>,
+    /// Length of the file; a commit appends past it.
+    pub len: u64,
+}
+
+/// Encode every block after its children, so that the checksum of each
+/// child is known by the time a reference to it is written. A block found in
+/// `stale` gets the checksum given there instead of its own, so that a block
+/// known to be corrupt keeps failing verification. A block `known` holds a
+/// checksum for is unchanged since it was written and is not encoded again;
+/// only such a block may be missing from `blocks`.
+fn encode_records(
+    blocks: &[Option<Block>], stale: &BTreeMap<BlockId, u32>, known: &[Option<u32>],
+) -> (Vec<Option<Vec<u8>>>, Vec<u32>) {
+    let mut records = vec![None; blocks.len()];
+    let mut sums = vec![0; blocks.len()];
+    let mut done = vec![false; blocks.len()];
+    for (idx, sum) in known.iter().enumerate() {
+        if let Some(sum) = sum {
+            sums[idx] = stale.get(&BlockId(idx)).copied().unwrap_or(*sum);
+            done[idx] = true;
+        }
+    }
+    for start in 0..blocks.len() {
+        let mut stack = vec![(BlockId(start), false)];
+        while let Some((id, expanded)) = stack.pop() {
+            if done[id.0] {
+                continue;
+            }
+            let block = blocks[id.0].as_ref().expect("a block that is not given is unchanged");
+            if !expanded {
+                stack.push((id, true));
+                stack.extend(block.children().into_iter().map(|child| (child, false)));
+                continue;
+            }
+            let record = encode_block(block, &sums);
+            sums[id.0] = match stale.get(&id) {
+                Some(&sum) => sum,
+                None => checksum(Kind::of(block), &record),
+            };
+            records[id.0] = Some(record);
+            done[id.0] = true;
+        }
+    }
+    (records, sums)
+}
+
+/// Lay out `records`, the block map and the tables as they follow `start`,
+/// a slot boundary of the image. A block without a record keeps the entry
+/// `base` holds for it; if `old`, the file `base` describes, is given, its
+/// record is copied over from there. Returns the bytes, the superblock that
+/// refers to them, for the caller to give a generation, and the new block
+/// map.
+fn lay_out(
+    start: usize, blocks: &[Option<Block>], records: &[Option<Vec<u8>>], base: &[MapEntry], mut old: Option<&mut File>,
+    tables: &Tables, sums: &[u32],
+) -> (Vec<u8>, SuperBlock, Vec<MapEntry>) {
+    let mut buf = Vec::new();
+    let mut map = Vec::with_capacity(blocks.len());
+    for (idx, record) in records.iter().enumerate() {
+        let slot = ((start + buf.len()) / BLOCK_SIZE) as u64;
+        let entry = match (record, &mut old) {
+            (Some(record), _) => {
+                let block = blocks[idx].as_ref().expect("an encoded block is given");
+                let size = record.len() as u32;
+                let (compression, record) = tables.compression.compress(record.clone());
+                buf.extend(&record);
+                MapEntry {
+                    slot,
+                    len: record.len() as u32,
+                    size,
+                    sum: sums[idx],
+                    kind: Kind::of(block),
+                    compression,
+                }
+            }
+            (None, Some(file)) => {
+                let entry = base[idx];
+                // a record that cannot be read anymore is written empty,
+                // which keeps it failing its checksum
+                let (len, size) = match read_stored(file, &entry) {
+                    Ok(record) => {
+                        buf.extend(&record);
+                        (entry.len, entry.size)
+                    }
+                    Err(_) => (0, 0),
+                };
+                MapEntry {
+                    slot,
+                    len,
+                    size,
+                    sum: sums[idx],
+                    ..entry
+                }
+            }
+            (None, None) => MapEntry {
+                sum: sums[idx],
+                ..base[idx]
+            },
+        };
+        buf.resize(slots(buf.len()) * BLOCK_SIZE, 0);
+        map.push(entry);
+    }
+    let map_start = (start + buf.len()) / BLOCK_SIZE;
+    for entry in &map {
+        entry.encode(&mut buf);
+    }
+    buf.resize(slots(buf.len()) * BLOCK_SIZE, 0);
+    let meta_start = (start + buf.len()) / BLOCK_SIZE;
+    let meta = encode_tables(tables, sums);
+    buf.extend(&meta);
+    buf.resize(slots(buf.len()) * BLOCK_SIZE, 0);
+    let superblock = SuperBlock {
+        version: VERSION,
+        block_size: BLOCK_SIZE as u32,
+        block_count: blocks.len() as u64,
+        root: tables.root,
+        map_start: map_start as u64,
+        meta_start: meta_start as u64,
+        meta_len: meta.len() as u64,
+        features: if tables.dedup { FEATURE_DEDUP } else { 0 },
+        compression: tables.compression,
+        root_sum: sums[tables.root.0],
+        generation: 0,
+    };
+    (buf, superblock, map)
+}
+
+/// The record of `entry` as stored in `file`.
+fn read_stored(file: &mut File, entry: &MapEntry) -> anyhow::Result<Vec<u8>> {
+    let mut buf = vec![0; entry.len as usize];
+    file.seek(SeekFrom::Start(entry.slot * BLOCK_SIZE as u64))?;
+    file.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+/// Byte offset of the slot the superblock of `generation` goes to. Two
+/// generations in a row never share one.
+fn superblock_slot(generation: u64) -> usize {
+    generation as usize % 2 * BLOCK_SIZE
+}
+
+/// Commit `blocks` and `tables` to the image file at `path`, whose current
+/// state is `base`, where only the blocks in `dirty` changed since; only
+/// those are encoded, and only they have to be given in `blocks`. Without a
+/// base, every block is. Once the file would be mostly garbage, or without a
+/// base, the whole image is written anew and replaces the file, the records
+/// of unchanged blocks copied over as they are. Returns the new state.
+pub fn commit(
+    path: &Path, blocks: &[Option<Block>], tables: &Tables, stale: &BTreeMap<BlockId, u32>, base: Option<&Committed>,
+    dirty: &BTreeSet<BlockId>,
+) -> anyhow::Result<Committed> {
+    let generation = base.map_or(1, |base| base.generation + 1);
+    let map = base.map_or(&[][..], |base| &base.map);
+    let known = (0..blocks.len().min(map.len()))
+        .map(|idx| (!dirty.contains(&BlockId(idx))).then_some(map[idx].sum))
+        .collect::<Vec<_>>();
+    let (records, sums) = encode_records(blocks, stale, &known);
+    let rewrite = || -> anyhow::Result<Committed> {
+        let mut old = match base {
+            Some(_) => Some(File::open(path)?),
+            None => None,
+        };
+        let (tail, mut superblock, map) = lay_out(2 * BLOCK_SIZE, blocks, &records, map, old.as_mut(), tables, &sums);
+        superblock.generation = generation;
+        // the superblock takes the slot of its generation, so that the next
+        // commit writes into the other one, left invalid
+        let mut image = vec![0; 2 * BLOCK_SIZE];
+        let slot = superblock_slot(generation);
+        image[slot..slot + BLOCK_SIZE].copy_from_slice(&superblock.encode());
+        image.extend(tail);
+        replace_file(path, &image)?;
+        let len = image.len() as u64;
+        Ok(Committed { generation, map, len })
+    };
+    let Some(base) = base else {
+        return rewrite();
+    };
+    // a crash during an earlier commit may have left a partial slot behind
+    let start = slots(base.len as usize) * BLOCK_SIZE;
+    let (tail, mut superblock, map) = lay_out(start, blocks, &records, &base.map, None, tables, &sums);
+    let records_slots = map.iter().map(|entry| slots(entry.len as usize)).sum::<usize>();
+    let live = (2 + records_slots) * BLOCK_SIZE + (start + tail.len() - superblock.map_start as usize * BLOCK_SIZE);
+    if start + tail.len() > 2 * live {
+        return rewrite();
+    }
+    superblock.generation = generation;
+    let mut file = OpenOptions::new().write(true).open(path)?;
+    file.seek(SeekFrom::Start(start as u64))?;
+    file.write_all(&tail)?;
+    file.sync_all()?;
+    // only now that everything it refers to is durable does the new tree
+    // become the current one
+    file.seek(SeekFrom::Start(superblock_slot(generation) as u64))?;
+    file.write_all(&superblock.encode())?;
+    file.sync_data()?;
+    Ok(Committed {
+        generation,
+        map,
+        len: (start + tail.len()) as u64,
+    })
+}
+
+/// Whether the file at `path` is an image of this format, that is, has a
+/// superblock in either slot, be it valid or not.
+pub fn is_image(path: &Path) -> anyhow::Result<bool> {
+    let mut buf = Vec::with_capacity(2 * BLOCK_SIZE);
+    File::open(path)?.take(2 * BLOCK_SIZE as u64).read_to_end(&mut buf)?;
+    Ok(buf.starts_with(&MAGIC) || buf.get(BLOCK_SIZE..).is_some_and(|slot| slot.starts_with(&MAGIC)))
+}
+
+/// Atomically replace the file at `path` with `contents`.
+pub fn replace_file(path: &Path, contents: &[u8]) -> anyhow::Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+    let mut file = File::create(&tmp)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    drop(file);
+    std::fs::rename(&tmp, path)?;
+    // make the rename itself durable
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    File::open(dir)?.sync_all()?;
+    Ok(())
+}
+
+/// A block as read from an image.
+pub struct Record {
+    pub block: Block,
+    /// Checksum of the record as read.
+    pub sum: u32,
+    /// Checksums the references of `block` carry, in the order of
+    /// `Block::children`.
+    pub child_sums: Vec<u32>,
+}
+
+/// Random access to the blocks of an image on disk.
+pub struct ImageReader {
+    file: File,
+    pub superblock: SuperBlock,
+    /// The block map as stored, cut short where the file is.
+    map: Vec<u8>,
+}
+
+impl ImageReader {
+    /// Open the image at `path` at its current superblock.
+    pub fn open(path: &Path) -> anyhow::Result<Self> {
+        let mut file = File::open(path)?;
+        let mut buf = Vec::with_capacity(2 * BLOCK_SIZE);
+        (&mut file).take(2 * BLOCK_SIZE as u64).read_to_end(&mut buf)?;
+        let first = SuperBlock::decode(&buf[..buf.len().min(BLOCK_SIZE)]);
+        let second = buf.get(BLOCK_SIZE..).and_then(|buf| SuperBlock::decode(buf).ok());
+        let superblock = match (first, second) {
+            (Ok(first), Some(second)) if second.generation > first.generation => second,
+            (Ok(first), _) => first,
+            (Err(_), Some(second)) => second,
+            (Err(err), None) => return Err(err),
+        };
+        let mut map = Vec::new();
+        file.seek(SeekFrom::Start(superblock.map_start * BLOCK_SIZE as u64))?;
+        (&mut file)
+            .take(superblock.block_count * MAP_ENTRY_SIZE as u64)
+            .read_to_end(&mut map)?;
+        Ok(ImageReader { file, superblock, map })
+    }
+    fn read_at(&mut self, offset: u64, len: usize) -> anyhow::Result<Vec<u8>> {
+        let mut buf = vec![0; len];
+        self.file.seek(SeekFrom::Start(offset))?;
+        self.file.read_exact(&mut buf)?;
+        Ok(buf)
+    }
+    /// The block map entry of `id`.
+    pub fn entry(&self, id: BlockId) -> anyhow::Result<MapEntry> {
+        if id.0 as u64 >= self.superblock.block_count {
+            anyhow::bail!("block {:?} out of range", id)
+        }
+        let offset = id.0 * MAP_ENTRY_SIZE;
+        MapEntry::decode(self.map.get(offset..).unwrap_or_default())
+    }
+    /// Read and decode the single block `id`.
+    pub fn read_block(&mut self, id: BlockId) -> anyhow::Result<Block> {
+        Ok(self.read_record(id)?.block)
+    }
+    /// Read and decode the single block `id`, keeping what it takes to
+    /// verify it.
+    pub fn read_record(&mut self, id: BlockId) -> anyhow::Result<Record> {
+        let entry = self.entry(id)?;
+        let record = entry.compression.decompress(read_stored(&mut self.file, &entry)?)?;
+        let sum = checksum(entry.kind, &record);
+        let (block, child_sums) = decode_block(entry.kind, &record)?;
+        Ok(Record { block, sum, child_sums })
+    }
+    pub fn read_tables(&mut self) -> anyhow::Result<Tables> {
+        let offset = self.superblock.meta_start * BLOCK_SIZE as u64;
+        let meta = self.read_at(offset, self.superblock.meta_len as usize)?;
+        decode_tables(&self.superblock, &meta)
+    }
+    /// Every record of the image; one that cannot be read or decoded does
+    /// not fail the others.
+    pub fn read_records(&mut self) -> Vec<anyhow::Result<Record>> {
+        (0..self.superblock.block_count as usize)
+            .map(|idx| self.read_record(BlockId(idx)))
+            .collect()
+    }
+    /// The state a commit on top of the image builds on; `None` if the next
+    /// commit is to rewrite the image.
+    pub fn committed(&mut self) -> anyhow::Result<Option<Committed>> {
+        let map = (0..self.superblock.block_count as usize)
+            .map(|idx| self.entry(BlockId(idx)))
+            .collect::<anyhow::Result<_>>();
+        // a damaged block map is not built on
+        let Ok(map) = map else {
+            return Ok(None);
+        };
+        Ok(Some(Committed {
+            generation: self.superblock.generation,
+            map,
+            len: self.file.metadata()?.len(),
+        }))
+    }
+}
@@ -0,0 +1,408 @@
+use crate::{
+    block::{Block, BlockId, BlockType, INode},
+    disk::{ImageReader, Kind, Record, Tables},
+    scrub,
+    view::FPath,
+    FileSys,
+};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Display,
+    path::PathBuf,
+};
+
+/// Something wrong with an image, as found by `fsck`. Every problem but an
+/// orphan names the tree and the path it was found at.
+#[derive(Clone, Debug)]
+pub enum Problem {
+    /// A reference to a block past the end of the image.
+    OutOfRange { id: BlockId, tree: String, path: FPath },
+    /// A block that cannot be read, or that does not match its checksum.
+    Corrupt {
+        id: BlockId,
+        tree: String,
+        path: FPath,
+        reason: String,
+    },
+    /// A block of another variant than the reference to it calls for.
+    WrongVariant {
+        id: BlockId,
+        tree: String,
+        path: FPath,
+        found: Kind,
+    },
+    /// A directory entry whose type disagrees with what its `INode` holds.
+    WrongEntryType {
+        id: BlockId,
+        tree: String,
+        path: FPath,
+        stored: BlockType,
+        found: BlockType,
+    },
+    /// An `INode` whose `ref_cnt` is not the number of entries naming it.
+    LinkCount {
+        id: BlockId,
+        tree: String,
+        path: FPath,
+        stored: usize,
+        found: usize,
+    },
+    /// A block in use that no tree reaches.
+    Orphan { id: BlockId },
+}
+
+impl Display for Problem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Problem::OutOfRange { id, tree, path } => write!(f, "{}\t{}\t{}\tout of range", id, tree, path),
+            Problem::Corrupt { id, tree, path, reason } => write!(f, "{}\t{}\t{}\t{}", id, tree, path, reason),
+            Problem::WrongVariant { id, tree, path, found } => {
+                write!(f, "{}\t{}\t{}\tunexpected {:?} block", id, tree, path, found)
+            }
+            Problem::WrongEntryType {
+                id,
+                tree,
+                path,
+                stored,
+                found,
+            } => write!(
+                f,
+                "{}\t{}\t{}\tentry says {:?}, holds {:?}",
+                id, tree, path, stored, found
+            ),
+            Problem::LinkCount {
+                id,
+                tree,
+                path,
+                stored,
+                found,
+            } => write!(
+                f,
+                "{}\t{}\t{}\tref_cnt {}, linked {} times",
+                id, tree, path, stored, found
+            ),
+            Problem::Orphan { id } => write!(f, "{}\t\t\torphaned", id),
+        }
+    }
+}
+
+/// Outcome of `FileSys::fsck`.
+pub struct Fsck {
+    pub problems: Vec<Problem>,
+    /// The file system with every problem fixed; dumping it repairs the
+    /// image. References to bad blocks are dropped: a directory loses the
+    /// entry, a file is cut short before the first bad block of its content.
+    /// A tree whose root is bad is dropped too, the checked-out branch being
+    /// emptied instead.
+    pub repaired: FileSys,
+}
+
+/// What is wrong with a reference.
+enum Fault {
+    OutOfRange,
+    Corrupt(String),
+    WrongVariant(Kind),
+}
+
+struct Checker<'a> {
+    records: &'a [anyhow::Result<Record>],
+    /// The blocks as read, fixed up as the check goes.
+    blocks: Vec<Block>,
+    visited: Vec<bool>,
+    problems: Vec<Problem>,
+    /// File `INode`s whose content lost a block, with the number of chunks
+    /// in front of it.
+    cuts: Vec<(BlockId, usize)>,
+}
+
+impl Checker<'_> {
+    /// What is wrong with a reference to `id` that carries `sum`, if anything,
+    /// given the variants it `admits`.
+    fn fault(&mut self, id: BlockId, sum: Option<u32>, admits: impl Fn(&Block) -> bool) -> Option<Fault> {
+        let Some(record) = self.records.get(id.0) else {
+            return Some(Fault::OutOfRange);
+        };
+        match record {
+            Err(err) => Some(Fault::Corrupt(err.to_string())),
+            Ok(record) => match sum {
+                Some(sum) if sum != record.sum => Some(Fault::Corrupt(format!(
+                    "checksum mismatch: expected {:08x}, found {:08x}",
+                    sum, record.sum
+                ))),
+                _ if !admits(&record.block) => Some(Fault::WrongVariant(Kind::of(&record.block))),
+                _ => None,
+            },
+        }
+    }
+    fn report(&mut self, fault: Fault, id: BlockId, tree: &str, path: &FPath) {
+        let (tree, path) = (tree.to_owned(), path.clone());
+        self.problems.push(match fault {
+            Fault::OutOfRange => Problem::OutOfRange { id, tree, path },
+            Fault::Corrupt(reason) => Problem::Corrupt { id, tree, path, reason },
+            Fault::WrongVariant(found) => Problem::WrongVariant { id, tree, path, found },
+        });
+    }
+    /// The type of node the good `INode` `id` holds, as far as its first
+    /// child tells.
+    fn holds(&self, id: BlockId) -> Option<BlockType> {
+        let Block::INode(inode) = &self.blocks[id.0] else {
+            return None;
+        };
+        match self.records.get(inode.children.first()?.0)? {
+            Ok(Record {
+                block: Block::DirEntries(_),
+                ..
+            }) => Some(BlockType::Dir),
+            Ok(Record {
+                block: Block::Data(_) | Block::Indirect(_),
+                ..
+            }) => Some(BlockType::File),
+            _ => None,
+        }
+    }
+    /// Check the tree below the good root `root`, dropping every bad
+    /// reference from `blocks`.
+    fn walk(&mut self, tree: &str, root: BlockId) {
+        let mut stack = vec![(root, BlockType::Dir, FPath::root())];
+        while let Some((id, btype, path)) = stack.pop() {
+            if std::mem::replace(&mut self.visited[id.0], true) {
+                continue;
+            }
+            let sums = match &self.records[id.0] {
+                Ok(record) => record.child_sums.clone(),
+                Err(_) => vec![],
+            };
+            let sum = |idx: usize| sums.get(idx).copied();
+            self.blocks[id.0] = match self.blocks[id.0].clone() {
+                Block::INode(mut inode) if btype == BlockType::Dir => {
+                    let mut kept = Vec::with_capacity(inode.children.len());
+                    for (idx, child) in inode.children.into_iter().enumerate() {
+                        match self.fault(child, sum(idx), |block| matches!(block, Block::DirEntries(_))) {
+                            Some(fault) => self.report(fault, child, tree, &path),
+                            None => {
+                                kept.push(child);
+                                stack.push((child, btype, path.clone()));
+                            }
+                        }
+                    }
+                    inode.children = kept;
+                    Block::INode(inode)
+                }
+                Block::DirEntries(mut entries) => {
+                    for (idx, slot) in entries.iter_mut().filter(|slot| slot.is_some()).enumerate() {
+                        let entry = slot.as_mut().expect("only live slots");
+                        let path = path.join(&entry.name);
+                        let inode = entry.inode;
+                        if let Some(fault) = self.fault(inode, sum(idx), |block| matches!(block, Block::INode(_))) {
+                            self.report(fault, inode, tree, &path);
+                            *slot = None;
+                            continue;
+                        }
+                        match self.holds(inode) {
+                            Some(found) if (found == BlockType::Dir) != (entry.btype == BlockType::Dir) => {
+                                self.problems.push(Problem::WrongEntryType {
+                                    id: inode,
+                                    tree: tree.to_owned(),
+                                    path: path.clone(),
+                                    stored: entry.btype,
+                                    found,
+                                });
+                                entry.btype = found;
+                            }
+                            _ => {}
+                        }
+                        stack.push((inode, entry.btype, path));
+                    }
+                    Block::DirEntries(entries)
+                }
+                Block::INode(INode { ref_cnt, children }) => {
+                    let (mut chunks, mut cut) = (0, false);
+                    let children = self.walk_content(tree, &path, children, &sum, &mut chunks, &mut cut);
+                    if cut {
+                        self.cuts.push((id, chunks));
+                    }
+                    Block::INode(INode { ref_cnt, children })
+                }
+                block @ (Block::Data(_) | Block::Indirect(_) | Block::Free) => block,
+            };
+        }
+    }
+    /// The part of the content below `ids`, the children of a file `INode` or
+    /// of an `Indirect` block, which must all be of one variant, that comes
+    /// in front of the first bad reference, which sets `cut`. An `Indirect`
+    /// block cut down on the way is replaced by a copy, as other versions of
+    /// the file may share it. `chunks` counts the `Data` blocks kept.
+    fn walk_content(
+        &mut self, tree: &str, path: &FPath, ids: Vec<BlockId>, sum: &dyn Fn(usize) -> Option<u32>, chunks: &mut usize,
+        cut: &mut bool,
+    ) -> Vec<BlockId> {
+        let mut level = None;
+        let mut kept = Vec::with_capacity(ids.len());
+        for (idx, mut child) in ids.into_iter().enumerate() {
+            let admits = |block: &Block| match level {
+                None => matches!(block, Block::Data(_) | Block::Indirect(_)),
+                Some(kind) => Kind::of(block) == kind,
+            };
+            if let Some(fault) = self.fault(child, sum(idx), admits) {
+                self.report(fault, child, tree, path);
+                *cut = true;
+                break;
+            }
+            level = Some(Kind::of(&self.blocks[child.0]));
+            match self.blocks[child.0].clone() {
+                Block::Indirect(below) => {
+                    let len = below.len();
+                    let sums = match &self.records[child.0] {
+                        Ok(record) => record.child_sums.clone(),
+                        Err(_) => vec![],
+                    };
+                    let below = self.walk_content(tree, path, below, &|idx| sums.get(idx).copied(), chunks, cut);
+                    if below.len() < len {
+                        child = BlockId(self.blocks.len());
+                        self.blocks.push(Block::Indirect(below));
+                    }
+                }
+                _ => *chunks += 1,
+            }
+            kept.push(child);
+            if *cut {
+                break;
+            }
+        }
+        kept
+    }
+    /// Check the `ref_cnt` of every `INode` in the tree below `root`, which
+    /// `walk` has cleaned up, against the entries naming it.
+    fn count_links(&mut self, tree: &str, root: BlockId) {
+        let mut links = BTreeMap::<BlockId, (usize, FPath)>::new();
+        let mut dirs = BTreeSet::new();
+        let mut stack = vec![(root, FPath::root())];
+        while let Some((dir, path)) = stack.pop() {
+            if !dirs.insert(dir) {
+                continue;
+            }
+            let Block::INode(inode) = &self.blocks[dir.0] else {
+                continue;
+            };
+            for &entries in &inode.children {
+                let Block::DirEntries(entries) = &self.blocks[entries.0] else {
+                    continue;
+                };
+                for entry in entries.iter().flatten() {
+                    let path = path.join(&entry.name);
+                    links.entry(entry.inode).or_insert((0, path.clone())).0 += 1;
+                    if entry.btype == BlockType::Dir {
+                        stack.push((entry.inode, path));
+                    }
+                }
+            }
+        }
+        for (id, (found, path)) in links {
+            let Block::INode(inode) = &mut self.blocks[id.0] else {
+                continue;
+            };
+            if inode.ref_cnt != found {
+                self.problems.push(Problem::LinkCount {
+                    id,
+                    tree: tree.to_owned(),
+                    path,
+                    stored: inode.ref_cnt,
+                    found,
+                });
+                inode.ref_cnt = found;
+            }
+        }
+    }
+}
+
+/// Which records can be reached from some tree of `tables`, following every
+/// reference of every record that can be read, good or bad.
+fn reachable(records: &[anyhow::Result<Record>], tables: &Tables) -> Vec<bool> {
+    let mut reached = vec![false; records.len()];
+    let mut stack = scrub::trees(tables)
+        .into_iter()
+        .map(|(_, root)| root)
+        .collect::<Vec<_>>();
+    while let Some(id) = stack.pop() {
+        match reached.get_mut(id.0) {
+            Some(reached) if !*reached => *reached = true,
+            _ => continue,
+        }
+        if let Ok(record) = &records[id.0] {
+            stack.extend(record.block.children());
+        }
+    }
+    reached
+}
+
+impl FileSys {
+    /// Check the image at `instance` without loading it: every reference must
+    /// be in range, match its checksum and lead to a block of the variant it
+    /// calls for, every entry must agree with the type of its `INode`, every
+    /// `ref_cnt` must match the entries naming the `INode` and every block in
+    /// use must be reachable from some tree.
+    pub fn fsck(instance: PathBuf) -> anyhow::Result<Fsck> {
+        let mut reader = ImageReader::open(&instance)?;
+        let mut tables = reader.read_tables()?;
+        let records = reader.read_records();
+        let mut checker = Checker {
+            blocks: records
+                .iter()
+                .map(|record| match record {
+                    Ok(record) => record.block.clone(),
+                    Err(_) => Block::Free,
+                })
+                .collect(),
+            visited: vec![false; records.len()],
+            records: &records,
+            problems: vec![],
+            cuts: vec![],
+        };
+        let is_inode = |block: &Block| matches!(block, Block::INode(_));
+        let mut bad_roots = BTreeSet::new();
+        let mut good = vec![];
+        for (tree, root) in scrub::trees(&tables) {
+            let sum = tables.root_sums.get(&root).copied();
+            match checker.fault(root, sum, is_inode) {
+                Some(fault) => {
+                    checker.report(fault, root, &tree, &FPath::root());
+                    bad_roots.insert(root);
+                }
+                None => {
+                    checker.walk(&tree, root);
+                    good.push((tree, root));
+                }
+            }
+        }
+        for (tree, root) in good {
+            checker.count_links(&tree, root);
+        }
+        let reached = reachable(&records, &tables);
+        for (idx, record) in records.iter().enumerate() {
+            let free = matches!(record, Ok(Record { block: Block::Free, .. }));
+            if !reached[idx] && !free {
+                checker.problems.push(Problem::Orphan { id: BlockId(idx) });
+            }
+        }
+        let Checker {
+            mut blocks,
+            problems,
+            cuts,
+            ..
+        } = checker;
+        if bad_roots.contains(&tables.root) {
+            tables.root = BlockId(blocks.len());
+            blocks.extend(FileSys::fs_new_blocks());
+        }
+        tables.branches.retain(|_, root| !bad_roots.contains(root));
+        tables.snapshots.retain(|_, root| !bad_roots.contains(root));
+        // orphans and whatever hung below bad blocks go with the garbage collection
+        let mut repaired = FileSys::fs_disk_init_with(instance, tables, blocks)?;
+        // a file that lost part of its content is truncated in front of it
+        if !cuts.is_empty() {
+            repaired.cut_files(&cuts);
+            repaired.gc();
+        }
+        Ok(Fsck { problems, repaired })
+    }
+}
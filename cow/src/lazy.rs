@@ -0,0 +1,142 @@
+use crate::{
+    block::{Block, BlockId, BlockType},
+    disk::{ImageReader, MapEntry},
+    scrub::{self, Expect},
+    FileSys,
+};
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Mutex,
+    },
+};
+
+/// The blocks of the image a file system was loaded from that have not been
+/// read yet. Their slots hold `Block::Free` until the first time they are
+/// indexed through `FileSys`, which reads them in.
+#[derive(Default)]
+pub(crate) struct Unread {
+    /// Whether each slot of the image is still to be read. Slots past the
+    /// image were never part of it.
+    flags: Box<[AtomicBool]>,
+    /// Number of slots still to be read.
+    left: AtomicUsize,
+    source: Mutex<Option<Source>>,
+}
+
+struct Source {
+    reader: ImageReader,
+    /// For blocks still to be read whose parent has been, the checksum and
+    /// the variant the reference to them calls for.
+    expect: HashMap<BlockId, (u32, Expect)>,
+}
+
+impl Unread {
+    /// Every block of the image `reader` reads, the root directories in
+    /// `roots` being expected to match the checksums given for them.
+    pub fn new(reader: ImageReader, roots: impl IntoIterator<Item = (BlockId, u32)>) -> Self {
+        let count = reader.superblock.block_count as usize;
+        let expect = roots
+            .into_iter()
+            .map(|(root, sum)| (root, (sum, Expect::INode(BlockType::Dir))))
+            .collect();
+        Unread {
+            flags: (0..count).map(|_| AtomicBool::new(true)).collect(),
+            left: AtomicUsize::new(count),
+            source: Mutex::new(Some(Source { reader, expect })),
+        }
+    }
+    /// Whether every block of the image has been read.
+    pub fn is_done(&self) -> bool {
+        self.left.load(Ordering::Acquire) == 0
+    }
+    /// Whether the block `id` is still to be read.
+    pub fn pending(&self, id: BlockId) -> bool {
+        self.flags.get(id.0).is_some_and(|flag| flag.load(Ordering::Acquire))
+    }
+    /// The block map entry of `id`, as long as the block is still to be read.
+    pub fn entry(&self, id: BlockId) -> Option<MapEntry> {
+        if !self.pending(id) {
+            return None;
+        }
+        self.source.lock().unwrap().as_ref()?.reader.entry(id).ok()
+    }
+    fn done(&self, source: &mut Source, id: BlockId) {
+        source.expect.remove(&id);
+        self.flags[id.0].store(false, Ordering::Release);
+        self.left.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+impl FileSys {
+    /// Read the block `id` of the loaded image in, if that is still to happen.
+    ///
+    /// The block is verified against the checksum the reference of its
+    /// parent carries, which is read first on any walk; only a block reached
+    /// otherwise is checked against its block map entry alone. A bad block
+    /// is replaced by a stand-in, see `scrub::stand_in`, and recorded as
+    /// corrupt. The blocks a bad block refers to keep the references it held,
+    /// as those cannot be trusted, until `gc` reclaims them.
+    pub(crate) fn read_in(&self, id: BlockId) {
+        if !self.unread.pending(id) {
+            return;
+        }
+        let mut source = self.unread.source.lock().unwrap();
+        // another walk may have read it in the meantime
+        if !self.unread.pending(id) {
+            return;
+        }
+        let source = source.as_mut().expect("slots to read come with an image");
+        let parent = source.expect.get(&id).copied();
+        let entry = source.reader.entry(id);
+        let record = source.reader.read_record(id);
+        let in_range = |block: &Block| block.children().iter().all(|child| child.0 < self.unread.flags.len());
+        let block = match (parent, record) {
+            (Some((sum, expect)), Ok(record))
+                if record.sum == sum && expect.admits(&record.block) && in_range(&record.block) =>
+            {
+                let children = expect.children(&record.block).into_iter().zip(record.child_sums);
+                for ((child, expect, _), sum) in children {
+                    if self.unread.pending(child) {
+                        source.expect.insert(child, (sum, expect));
+                    }
+                }
+                record.block
+            }
+            (None, Ok(record))
+                if entry.as_ref().is_ok_and(|entry| entry.sum == record.sum) && in_range(&record.block) =>
+            {
+                record.block
+            }
+            (parent, record) => {
+                let expected = parent.or_else(|| {
+                    let entry = entry.as_ref().ok()?;
+                    Some((entry.sum, Expect::of(entry.kind)?))
+                });
+                match expected {
+                    Some((sum, expect)) => {
+                        self.corrupt.write().unwrap().insert(id, sum);
+                        scrub::stand_in(record.ok().map(|record| record.block), expect)
+                    }
+                    // a free slot that cannot be read is free all the same
+                    None => Block::Free,
+                }
+            }
+        };
+        *self.blocks[id].write().unwrap() = block;
+        self.unread.done(source, id);
+    }
+    /// Give up on reading the block `id` of the loaded image, as its slot is
+    /// being reused.
+    pub(crate) fn skip_read(&self, id: BlockId) {
+        if !self.unread.pending(id) {
+            return;
+        }
+        let mut source = self.unread.source.lock().unwrap();
+        if self.unread.pending(id) {
+            let source = source.as_mut().expect("slots to read come with an image");
+            self.unread.done(source, id);
+        }
+    }
+}
@@ -3,18 +3,130 @@
 pub mod view;
 pub mod block;
 pub mod stepper;
+pub mod snapshot;
+pub mod branch;
+pub mod diff;
+pub mod merge;
+pub mod transaction;
+pub mod reader;
+pub mod reclaim;
+pub mod content;
+pub mod dedup;
+pub mod scrub;
+pub mod fsck;
+pub mod alloc;
+pub mod disk;
+pub mod lazy;
 
-use block::{Block, BlockId, BlockType, DirEntry, INode};
+pub use interface::*;
+
+use alloc::Allocator;
+use block::{Block, BlockId, BlockTable, BlockType, Data, DirEntry, INode, DIR_ENTRIES_PER_BLOCK};
+use dedup::{DedupIndex, SpaceReport};
+use disk::{Committed, Compression, ImageReader, Tables};
+use lazy::Unread;
+use serde::{Deserialize, Serialize};
 use std::{
-    path::PathBuf,
-    sync::{Arc, RwLock},
+    cell::Cell,
+    collections::{BTreeMap, BTreeSet},
+    path::{Path, PathBuf},
+    sync::{Mutex, MutexGuard, RwLock},
 };
-use stepper::Stepper;
+use stepper::{Root, Stepper};
 use view::FPath;
 
+pub(crate) type CowFsError = FileSystemError<FPath>;
+
+/// A copy-on-write file system.
+///
+/// The operations of `IFileSystem` are also available through `&self`, so
+/// that many threads can share one file system. Those that change the tree
+/// take turns on `writer`, while the operations of transactions, which build
+/// trees of their own, share it and only take turns to commit. Walks lock
+/// the blocks along their path hand over hand, see `Stepper`; as blocks in
+/// use are never modified, only reclaimed, all walks share their locks and
+/// neither block each other nor writers.
+///
+/// Locks are taken in this order, which rules out deadlocks: `writer`, then
+/// `root`, then the locks of blocks, a parent before its child, and last
+/// `books`, then the image blocks are read in from, see `Unread`, and last
+/// `corrupt`. Block locks are also taken with `books` held, but never in a
+/// way that waits: `fresh` only writes blocks into slots that are free or
+/// newly appended, which no walk can reach, and reclaiming a block takes its
+/// lock with `try_write` and puts the free off while a walk holds it. A
+/// block is read in with the image held, into a slot nothing else locks
+/// until it has been. A walk lets go of its blocks before the tree it made is
+/// installed.
 pub struct FileSys {
     pub instance: PathBuf,
-    pub blocks: Vec<Arc<RwLock<Block>>>,
+    /// Blocks of the loaded image that are not read in yet hold `Block::Free`;
+    /// indexing `FileSys` reads them in.
+    pub blocks: BlockTable,
+    /// Root of the checked-out branch. A walk only holds it until it has
+    /// locked the root block.
+    pub(crate) root: RwLock<BlockId>,
+    pub(crate) snapshots: BTreeMap<String, BlockId>,
+    /// Name of the branch whose root is `root`.
+    pub(crate) head: String,
+    /// Roots of all branches except `head`.
+    pub(crate) branches: BTreeMap<String, BlockId>,
+    pub(crate) books: Mutex<Books>,
+    /// What `fs_disk_dump` compresses the blocks with.
+    pub compression: Compression,
+    /// Blocks found corrupt when the image was loaded, with the checksum their
+    /// parent holds for them. They are kept as stand-ins that every walk
+    /// refuses to enter, and written back with that same checksum, so that
+    /// they stay corrupt until removed.
+    pub(crate) corrupt: RwLock<BTreeMap<BlockId, u32>>,
+    /// Held exclusively by whatever swaps the root of the checked-out branch
+    /// or reclaims blocks, and shared by operations of transactions, which do
+    /// neither, for as long as they run.
+    pub(crate) writer: RwLock<()>,
+    /// What the instance file holds, as of the last commit; `None` if the
+    /// next one is to rewrite it. Only taken under `writer`.
+    pub(crate) committed: Mutex<Option<Committed>>,
+    pub(crate) unread: Unread,
+}
+
+/// What writers keep track of besides the blocks.
+pub(crate) struct Books {
+    /// Number of references to each block, from other blocks and from roots.
+    pub refs: Vec<usize>,
+    /// Blocks allocated since the last root update.
+    pub pending: Vec<BlockId>,
+    /// Blocks nothing refers to anymore that a walk still held when they were
+    /// to be freed.
+    pub deferred: Vec<BlockId>,
+    pub alloc: Allocator,
+    /// Present iff identical `Data` blocks are deduplicated.
+    pub dedup: Option<DedupIndex>,
+    /// Slots whose block changed since the last commit.
+    pub dirty: BTreeSet<BlockId>,
+    /// Roots pinned by readers and open transactions, each holding a reference.
+    pub pins: Vec<BlockId>,
+    /// Pins let go of while `writer` was taken, for the next `settle` to drop.
+    pub unpinned: Vec<BlockId>,
+}
+
+/// The JSON debug export of a whole `FileSys`.
+#[derive(Serialize, Deserialize)]
+struct Image {
+    root: BlockId,
+    #[serde(default)]
+    snapshots: BTreeMap<String, BlockId>,
+    #[serde(default = "branch::main")]
+    head: String,
+    #[serde(default)]
+    branches: BTreeMap<String, BlockId>,
+    /// Missing in images written before the allocator existed, in which
+    /// case it is rebuilt from the blocks.
+    #[serde(default)]
+    alloc: Option<Allocator>,
+    #[serde(default)]
+    dedup: bool,
+    #[serde(default)]
+    compression: Compression,
+    blocks: Vec<Block>,
 }
 
 impl FileSys {
@@ -24,41 +136,817 @@ impl FileSys {
             children: vec![],
         })]
     }
-    fn fs_disk_init_with_blocks(instance: PathBuf, blocks: Vec<Block>) -> anyhow::Result<FileSys> {
-        let blocks = blocks
-            .into_iter()
-            .map(|block| Arc::new(RwLock::new(block)))
-            .collect::<Vec<_>>();
-        Ok(FileSys { instance, blocks })
+    fn fs_new_tables() -> Tables {
+        Tables {
+            root: BlockId(0),
+            snapshots: BTreeMap::new(),
+            head: branch::main(),
+            branches: BTreeMap::new(),
+            alloc: Allocator::with_used(1),
+            dedup: false,
+            compression: Compression::Raw,
+            root_sums: BTreeMap::new(),
+            refs: vec![],
+        }
+    }
+    /// A file system of `blocks`, whose reference counts are rebuilt.
+    fn fs_disk_init_with(instance: PathBuf, tables: Tables, blocks: Vec<Block>) -> anyhow::Result<FileSys> {
+        let dedup = tables.dedup;
+        let mut fs = Self::fs_assemble(instance, tables, blocks.into_iter().collect(), Unread::default());
+        // the blocks come without reference counts, count them and drop any
+        // garbage
+        fs.gc();
+        fs.set_dedup(dedup);
+        Ok(fs)
+    }
+    /// A file system of `blocks`, with the reference counts of `tables`.
+    fn fs_assemble(instance: PathBuf, tables: Tables, blocks: BlockTable, unread: Unread) -> FileSys {
+        let Tables {
+            root,
+            snapshots,
+            head,
+            branches,
+            alloc,
+            dedup: _,
+            compression,
+            root_sums: _,
+            mut refs,
+        } = tables;
+        refs.resize(blocks.len(), 0);
+        let books = Books {
+            refs,
+            pending: vec![],
+            deferred: vec![],
+            alloc,
+            dedup: None,
+            dirty: BTreeSet::new(),
+            pins: vec![],
+            unpinned: vec![],
+        };
+        FileSys {
+            instance,
+            blocks,
+            root: RwLock::new(root),
+            snapshots,
+            head,
+            branches,
+            books: Mutex::new(books),
+            compression,
+            corrupt: RwLock::new(BTreeMap::new()),
+            writer: RwLock::new(()),
+            committed: Mutex::new(None),
+            unread,
+        }
+    }
+    fn fs_tables(&self) -> Tables {
+        let alloc = self.books().alloc.clone();
+        Tables {
+            root: self.root(),
+            snapshots: self.snapshots.clone(),
+            head: self.head.clone(),
+            branches: self.branches.clone(),
+            alloc,
+            dedup: self.dedup_enabled(),
+            compression: self.compression,
+            root_sums: BTreeMap::new(),
+            refs: vec![],
+        }
+    }
+    fn fs_blocks(&self) -> Vec<Block> {
+        (0..self.blocks.len())
+            .map(|idx| self[BlockId(idx)].read().unwrap().clone())
+            .collect()
     }
     pub fn fs_disk_init(instance: PathBuf) -> anyhow::Result<FileSys> {
-        let blocks = FileSys::fs_new_blocks();
-        Self::fs_disk_init_with_blocks(instance, blocks)
+        Self::fs_disk_init_with(instance, FileSys::fs_new_tables(), FileSys::fs_new_blocks())
     }
+    /// Load the image at `instance`. Only its tables are read right away;
+    /// each block is read the first time it is used and verified against its
+    /// checksum then. Bad blocks do not fail the load; walking into one
+    /// fails instead, and `corrupt_blocks` lists those found so far.
+    ///
+    /// An instance in the JSON format of earlier releases is imported
+    /// instead, see `fs_legacy_load`.
     pub fn fs_disk_load(instance: PathBuf) -> anyhow::Result<FileSys> {
-        let blocks: Vec<_> = serde_json::from_str(&std::fs::read_to_string(&instance)?)?;
-        Self::fs_disk_init_with_blocks(instance, blocks)
+        if !disk::is_image(&instance)? {
+            return Self::fs_legacy_load(instance);
+        }
+        let mut reader = ImageReader::open(&instance)?;
+        let tables = reader.read_tables()?;
+        let committed = reader.committed()?;
+        let count = reader.superblock.block_count as usize;
+        if tables.refs.len() != count {
+            anyhow::bail!("{} reference counts for {} blocks", tables.refs.len(), count)
+        }
+        let trees = scrub::trees(&tables);
+        if let Some((tree, _)) = trees.iter().find(|(_, root)| root.0 >= count) {
+            anyhow::bail!("root of {} out of range", tree)
+        }
+        let roots = trees
+            .into_iter()
+            .map(|(_, root)| (root, tables.root_sums.get(&root).copied().unwrap_or_default()));
+        let unread = Unread::new(reader, roots);
+        let dedup = tables.dedup;
+        let blocks = (0..count).map(|_| Block::Free).collect();
+        let mut fs = Self::fs_assemble(instance, tables, blocks, unread);
+        if committed.is_none() {
+            // a damaged block map is not built on, and the rewrite of the
+            // next commit needs every block
+            (0..count).for_each(|idx| fs.read_in(BlockId(idx)));
+        }
+        fs.set_dedup(dedup);
+        *fs.committed.get_mut().unwrap() = committed;
+        Ok(fs)
     }
-    pub fn fs_disk_dump(&self) -> anyhow::Result<()> {
-        let blocks = self
-            .blocks
-            .iter()
-            .map(|block| block.read().unwrap().clone())
+    /// Load an instance in the JSON format of earlier releases: a bare list
+    /// of blocks, with the root directory at `BlockId(0)` and every file
+    /// whole in `Data` blocks of any size, which are split into chunks. The
+    /// next `fs_disk_dump` rewrites the instance as a binary image.
+    fn fs_legacy_load(instance: PathBuf) -> anyhow::Result<FileSys> {
+        let blocks: Vec<Block> = serde_json::from_str(&std::fs::read_to_string(&instance)?)?;
+        let tables = Tables {
+            alloc: Allocator::with_used(blocks.len()),
+            ..FileSys::fs_new_tables()
+        };
+        let mut fs = Self::fs_disk_init_with(instance, tables, blocks)?;
+        if fs.rechunk() {
+            fs.gc();
+        }
+        Ok(fs)
+    }
+    /// Commit the current state to the instance file, see `disk::commit`:
+    /// only changed blocks are written, followed by a flip of the superblock,
+    /// so a crash leaves either the image of the last commit or the new one.
+    /// Returns how much the sharing of `Data` blocks saves in the image.
+    pub fn fs_disk_dump(&self) -> anyhow::Result<SpaceReport> {
+        // a consistent image needs the tree to hold still while it is copied
+        let _writer = self.writer.write().unwrap();
+        self.settle();
+        let mut committed = self.committed.lock().unwrap();
+        let mut tables = self.fs_tables();
+        // Trees that only readers and open transactions hold are no part of
+        // the image, which has no root for them: the blocks only they keep
+        // are written as free, by every commit they stay in use for.
+        let (refs, hidden) = self.image_refs();
+        tables.refs = refs;
+        // blocks not read in yet are unchanged, and left where they are
+        let mut blocks = (0..self.blocks.len())
+            .map(BlockId)
+            .map(|id| (!self.unread.pending(id)).then(|| self[id].read().unwrap().clone()))
             .collect::<Vec<_>>();
-        let s = serde_json::to_string(&blocks)?;
-        print!(
-            "Instance located at `{}` will be rewritten. Proceed? [../^C]",
-            self.instance.display()
+        for &id in &hidden {
+            blocks[id.0] = Some(Block::Free);
+            tables.alloc.release(id);
+        }
+        let dirty = std::mem::take(&mut self.books().dirty);
+        let result = disk::commit(
+            &self.instance,
+            &blocks,
+            &tables,
+            &self.corrupt.read().unwrap(),
+            committed.as_ref(),
+            &dirty.union(&hidden).copied().collect(),
         );
-        std::io::stdin().read_line(&mut String::new())?;
-        std::fs::write(&self.instance, s)?;
+        self.books().dirty.extend(hidden);
+        match result {
+            Ok(state) => *committed = Some(state),
+            Err(err) => {
+                self.books().dirty.extend(dirty);
+                return Err(err);
+            }
+        }
+        Ok(self.space_report())
+    }
+    /// Write the whole file system to `path` as JSON, for debugging.
+    pub fn fs_json_export(&self, path: &Path) -> anyhow::Result<()> {
+        let Tables {
+            root,
+            snapshots,
+            head,
+            branches,
+            alloc,
+            dedup,
+            compression,
+            root_sums: _,
+            refs: _,
+        } = self.fs_tables();
+        let image = Image {
+            root,
+            snapshots,
+            head,
+            branches,
+            alloc: Some(alloc),
+            dedup,
+            compression,
+            blocks: self.fs_blocks(),
+        };
+        std::fs::write(path, serde_json::to_string_pretty(&image)?)?;
         Ok(())
     }
-    pub fn root() -> BlockId {
-        BlockId(0)
+    /// Read a JSON export back into a file system backed by `instance`.
+    pub fn fs_json_import(instance: PathBuf, path: &Path) -> anyhow::Result<FileSys> {
+        let Image {
+            root,
+            snapshots,
+            head,
+            branches,
+            alloc,
+            dedup,
+            compression,
+            blocks,
+        } = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+        let alloc = alloc.unwrap_or_else(|| Allocator::with_used(blocks.len()));
+        let tables = Tables {
+            root,
+            snapshots,
+            head,
+            branches,
+            alloc,
+            dedup,
+            compression,
+            root_sums: BTreeMap::new(),
+            refs: vec![],
+        };
+        Self::fs_disk_init_with(instance, tables, blocks)
+    }
+    /// The `INode` of the root directory of the checked-out branch.
+    pub fn root(&self) -> BlockId {
+        *self.root.read().unwrap()
+    }
+}
+
+/* ------------------------------ block helpers ----------------------------- */
+
+impl FileSys {
+    /// Allocate `block`, which takes a reference to each of its children.
+    /// Slots of freed blocks are reused before the block list grows.
+    pub fn fresh(&self, block: Block) -> BlockId {
+        let mut books = self.books();
+        let id = books.alloc.alloc();
+        for child in block.children() {
+            books.refs[child.0] += 1;
+        }
+        if id.0 == self.blocks.len() {
+            self.blocks.push(block);
+            books.refs.push(0);
+        } else {
+            // a free slot, which no walk can reach; one of the loaded image
+            // need not be read in
+            self.skip_read(id);
+            *self.blocks[id].write().unwrap() = block;
+            books.refs[id.0] = 0;
+        }
+        books.pending.push(id);
+        books.dirty.insert(id);
+        id
+    }
+    pub fn inode(&self, id: BlockId) -> INode {
+        self[id].read().unwrap().clone().inode()
+    }
+    pub fn dir_entries(&self, id: BlockId) -> Vec<Option<DirEntry>> {
+        self[id].read().unwrap().clone().dir_entries()
+    }
+    pub fn data(&self, id: BlockId) -> Data {
+        self[id].read().unwrap().clone().data()
+    }
+    /// All live entries of the directory whose `INode` is `dir`.
+    pub fn entries(&self, dir: BlockId) -> Vec<DirEntry> {
+        let inode = self.inode(dir);
+        inode
+            .children
+            .iter()
+            .flat_map(|&entries| self.dir_entries(entries))
+            .flatten()
+            .collect()
+    }
+    /// Locate `name` in the directory `dir`, returning the `DirEntries` block,
+    /// the slot within it and the entry itself.
+    pub fn find_entry(&self, dir: BlockId, name: &str) -> Option<(BlockId, usize, DirEntry)> {
+        let inode = self.inode(dir);
+        for entries_id in inode.children {
+            let entries = self.dir_entries(entries_id);
+            for (slot, entry) in entries.into_iter().enumerate() {
+                match entry {
+                    Some(entry) if entry.name == name => return Some((entries_id, slot, entry)),
+                    _ => {}
+                }
+            }
+        }
+        None
+    }
+    /// A copy of the directory `inode` with `entry` in its first free slot.
+    /// Only the `DirEntries` block that changes is shadowed.
+    pub fn insert_entry(&self, mut inode: INode, entry: DirEntry) -> INode {
+        for idx in 0..inode.children.len() {
+            let mut entries = self.dir_entries(inode.children[idx]);
+            if let Some(slot) = entries.iter_mut().find(|slot| slot.is_none()) {
+                *slot = Some(entry);
+            } else if entries.len() < DIR_ENTRIES_PER_BLOCK {
+                entries.push(Some(entry));
+            } else {
+                continue;
+            }
+            inode.children[idx] = self.fresh(Block::DirEntries(entries));
+            return inode;
+        }
+        let entries = self.fresh(Block::DirEntries(vec![Some(entry)]));
+        inode.children.push(entries);
+        inode
+    }
+    /// A copy of the directory `inode` with `slot` of `entries_id` cleared.
+    /// A `DirEntries` block left with no live entry is dropped altogether.
+    pub fn remove_entry(&self, mut inode: INode, entries_id: BlockId, slot: usize) -> INode {
+        let mut entries = self.dir_entries(entries_id);
+        entries[slot] = None;
+        if entries.iter().all(Option::is_none) {
+            inode.children.retain(|&id| id != entries_id);
+        } else {
+            let fresh = self.fresh(Block::DirEntries(entries));
+            for id in inode.children.iter_mut() {
+                if *id == entries_id {
+                    *id = fresh;
+                }
+            }
+        }
+        inode
+    }
+    /// Point every entry of `tree` that names `old` at `new` instead, shadowing
+    /// each directory on the way. Needed when an `INode` with more than one
+    /// link is shadowed; costs a walk over the whole tree.
+    fn relink(&self, tree: Tree<'_>, old: BlockId, new: BlockId) {
+        if let Some(root) = self.relink_dir(tree.root(self), old, new) {
+            self.install(tree, root);
+        }
+    }
+    fn relink_dir(&self, dir: BlockId, old: BlockId, new: BlockId) -> Option<BlockId> {
+        let mut inode = self.inode(dir);
+        let mut changed = false;
+        for idx in 0..inode.children.len() {
+            let mut entries = self.dir_entries(inode.children[idx]);
+            let mut dirty = false;
+            for entry in entries.iter_mut().flatten() {
+                if entry.inode == old {
+                    entry.inode = new;
+                    dirty = true;
+                } else if entry.btype == BlockType::Dir {
+                    if let Some(shadow) = self.relink_dir(entry.inode, old, new) {
+                        entry.inode = shadow;
+                        dirty = true;
+                    }
+                }
+            }
+            if dirty {
+                inode.children[idx] = self.fresh(Block::DirEntries(entries));
+                changed = true;
+            }
+        }
+        changed.then(|| self.fresh(Block::INode(inode)))
+    }
+    /// Install `inode` as the new version of the file `stepper` stands on in
+    /// `tree`.
+    fn replace_file(&self, tree: Tree<'_>, stepper: Stepper, inode: INode) {
+        if stepper.inode().ref_cnt > 1 {
+            let old = stepper.current;
+            drop(stepper);
+            let new = self.fresh(Block::INode(inode));
+            self.relink(tree, old, new);
+        } else {
+            stepper.shadow(self, tree, inode);
+        }
+    }
+    /// Resolve `path` under `root` to the type and `INode` of the node it names.
+    pub fn traverse(&self, root: impl Into<Root>, path: FPath) -> Result<(BlockType, BlockId), CowFsError> {
+        let stepper = Stepper::walk(self, root.into(), path, true)?;
+        Ok((stepper.btype, stepper.current))
+    }
+    /// Walk to the directory `path` under `root`.
+    fn walk_dir(&self, root: impl Into<Root>, path: FPath) -> Result<Stepper<'_>, CowFsError> {
+        let stepper = Stepper::walk(self, root.into(), path.clone(), true)?;
+        match stepper.btype {
+            BlockType::Dir => {
+                self.check_dir(stepper.current, || stepper.physical(self))?;
+                Ok(stepper)
+            }
+            _ => Err(FileSystemError::IndexOnFile(path)),
+        }
+    }
+    /// Walk to the file `path` under `root`.
+    fn walk_file(&self, root: impl Into<Root>, path: FPath) -> Result<Stepper<'_>, CowFsError> {
+        let stepper = Stepper::walk(self, root.into(), path.clone(), true)?;
+        match stepper.btype {
+            BlockType::File => {
+                self.check_tree(stepper.current, || stepper.physical(self))?;
+                Ok(stepper)
+            }
+            _ => Err(FileSystemError::OperateFileOnDir(path)),
+        }
+    }
+    /// The target stored in the symbolic link `INode` `id`.
+    pub(crate) fn link_target(&self, id: BlockId) -> String {
+        let target = self.read_range(&self.inode(id).children, 0, usize::MAX);
+        String::from_utf8_lossy(&target).into_owned()
+    }
+    /// Walk to the parent of `path` in `tree`, checking that it has no entry
+    /// named like `path`.
+    fn walk_vacant(&self, tree: Tree<'_>, path: FPath) -> Result<(Stepper<'_>, String), CowFsError> {
+        let (parent, name) = path.clone().parent().ok_or(FileSystemError::OperateOnRoot)?;
+        let stepper = self.walk_dir(tree.root(self), parent)?;
+        if self.find_entry(stepper.current, &name).is_some() {
+            Err(FileSystemError::AlreadyExists(path))?
+        }
+        Ok((stepper, name))
+    }
+    pub fn metadata_at(&self, root: impl Into<Root>, path: FPath) -> Result<Meta, CowFsError> {
+        let stepper = Stepper::walk(self, root.into(), path.clone(), false)?;
+        let (btype, inode) = (stepper.btype, stepper.current);
+        self.check(inode, || stepper.physical(self))?;
+        let nlink = self.inode(inode).ref_cnt;
+        Ok(Meta { btype, inode, nlink })
     }
-    pub fn create(&mut self, path: String) -> anyhow::Result<()> {
-        let mut stepper = Stepper::new(self, FPath::new(path.as_str())?)?;
+    pub fn read_file_at(&self, root: impl Into<Root>, path: FPath) -> Result<Data, CowFsError> {
+        let stepper = self.walk_file(root, path)?;
+        Ok(Data::from(self.read_range(&stepper.inode().children, 0, usize::MAX)))
+    }
+    pub fn read_range_at(
+        &self, root: impl Into<Root>, path: FPath, offset: usize, len: usize,
+    ) -> Result<Data, CowFsError> {
+        let stepper = self.walk_file(root, path)?;
+        Ok(Data::from(self.read_range(&stepper.inode().children, offset, len)))
+    }
+    pub fn read_dir_at(&self, root: impl Into<Root>, path: FPath) -> Result<Vec<String>, CowFsError> {
+        let stepper = self.walk_dir(root, path)?;
+        Ok(self
+            .entries(stepper.current)
+            .into_iter()
+            .map(|entry| entry.name)
+            .collect())
+    }
+    pub fn read_link_at(&self, root: impl Into<Root>, path: FPath) -> Result<String, CowFsError> {
+        let stepper = Stepper::walk(self, root.into(), path.clone(), false)?;
+        if stepper.btype != BlockType::Symlink {
+            Err(FileSystemError::NotSymlink(path.clone()))?
+        }
+        self.check_tree(stepper.current, || stepper.physical(self))?;
+        Ok(self.link_target(stepper.current))
+    }
+    fn create_node(&self, tree: Tree<'_>, path: FPath, btype: BlockType) -> Result<(), CowFsError> {
+        let (stepper, name) = self.walk_vacant(tree, path)?;
+        let inode = self.fresh(Block::INode(INode {
+            ref_cnt: 1,
+            children: vec![],
+        }));
+        let dir = self.insert_entry(stepper.inode(), DirEntry { name, btype, inode });
+        stepper.shadow(self, tree, dir);
         Ok(())
     }
 }
+
+/* ---------------------------------- meta ---------------------------------- */
+
+#[derive(Clone, Debug)]
+pub struct Meta {
+    pub btype: BlockType,
+    pub inode: BlockId,
+    /// `ref_cnt` of the `INode`
+    pub nlink: usize,
+}
+
+impl IMeta for Meta {
+    fn is_file(&self) -> bool {
+        self.btype == BlockType::File
+    }
+
+    fn is_dir(&self) -> bool {
+        self.btype == BlockType::Dir
+    }
+
+    fn is_symlink(&self) -> bool {
+        self.btype == BlockType::Symlink
+    }
+
+    fn nlink(&self) -> usize {
+        self.nlink
+    }
+}
+
+/* ----------------------------- implementation ----------------------------- */
+
+impl<'fs> IReadFileSystem<'fs> for FileSys {
+    type Path<'p> = FPath;
+
+    type Meta = Meta;
+
+    type Data = Data;
+
+    fn metadata(&self, path: Self::Path<'fs>) -> Result<Self::Meta, CowFsError> {
+        self.metadata_at(Root::Head, path)
+    }
+
+    fn read_file(&self, path: Self::Path<'fs>) -> Result<Self::Data, CowFsError> {
+        self.read_file_at(Root::Head, path)
+    }
+
+    fn read_at(&self, path: Self::Path<'fs>, offset: usize, len: usize) -> Result<Self::Data, CowFsError> {
+        self.read_range_at(Root::Head, path, offset, len)
+    }
+
+    fn read_dir(&self, path: Self::Path<'fs>) -> Result<Vec<String>, CowFsError> {
+        self.read_dir_at(Root::Head, path)
+    }
+
+    fn read_link(&self, path: Self::Path<'fs>) -> Result<String, CowFsError> {
+        self.read_link_at(Root::Head, path)
+    }
+}
+
+/* ------------------------------- operations ------------------------------- */
+
+/// The tree an operation changes.
+#[derive(Clone, Copy)]
+pub(crate) enum Tree<'t> {
+    /// That of the checked-out branch.
+    Head,
+    /// That of a transaction, whose root is kept here.
+    Work(&'t Cell<BlockId>),
+}
+
+impl Tree<'_> {
+    pub fn root(self, fs: &FileSys) -> BlockId {
+        match self {
+            Tree::Head => fs.root(),
+            Tree::Work(root) => root.get(),
+        }
+    }
+}
+
+// The operations of `IFileSystem`, through `&self`. Each holds `writer` while
+// it builds the new tree and installs its root.
+impl FileSys {
+    pub fn create_file(&self, path: FPath) -> Result<(), CowFsError> {
+        let _writer = self.writer.write().unwrap();
+        self.create_file_in(Tree::Head, path)
+    }
+
+    pub fn write_file(&self, path: FPath, data: Data) -> Result<(), CowFsError> {
+        let _writer = self.writer.write().unwrap();
+        self.write_file_in(Tree::Head, path, data)
+    }
+
+    pub fn write_at(&self, path: FPath, offset: usize, data: Data) -> Result<(), CowFsError> {
+        let _writer = self.writer.write().unwrap();
+        self.write_at_in(Tree::Head, path, offset, data)
+    }
+
+    pub fn truncate(&self, path: FPath, len: usize) -> Result<(), CowFsError> {
+        let _writer = self.writer.write().unwrap();
+        self.truncate_in(Tree::Head, path, len)
+    }
+
+    pub fn append(&self, path: FPath, data: Data) -> Result<(), CowFsError> {
+        let _writer = self.writer.write().unwrap();
+        self.append_in(Tree::Head, path, data)
+    }
+
+    pub fn create_dir(&self, path: FPath) -> Result<(), CowFsError> {
+        let _writer = self.writer.write().unwrap();
+        self.create_dir_in(Tree::Head, path)
+    }
+
+    pub fn create_link(&self, path: FPath, target: FPath) -> Result<(), CowFsError> {
+        let _writer = self.writer.write().unwrap();
+        self.create_link_in(Tree::Head, path, target)
+    }
+
+    pub fn create_symlink(&self, path: FPath, target: String) -> Result<(), CowFsError> {
+        let _writer = self.writer.write().unwrap();
+        self.create_symlink_in(Tree::Head, path, target)
+    }
+
+    pub fn rename(&self, from: FPath, to: FPath) -> Result<(), CowFsError> {
+        let _writer = self.writer.write().unwrap();
+        self.rename_in(Tree::Head, from, to)
+    }
+
+    pub fn remove(&self, path: FPath) -> Result<(), CowFsError> {
+        let _writer = self.writer.write().unwrap();
+        self.remove_in(Tree::Head, path)
+    }
+}
+
+// The operations proper, on `tree`, with `writer` held by the caller: shared
+// for the tree of a transaction, exclusively for that of the branch.
+impl FileSys {
+    pub(crate) fn create_file_in(&self, tree: Tree<'_>, path: FPath) -> Result<(), CowFsError> {
+        self.create_node(tree, path, BlockType::File)
+    }
+
+    pub(crate) fn write_file_in(&self, tree: Tree<'_>, path: FPath, data: Data) -> Result<(), CowFsError> {
+        let stepper = self.walk_file(tree.root(self), path)?;
+        let mut inode = stepper.inode();
+        inode.children = self.write_range(vec![], 0, &data.data);
+        self.replace_file(tree, stepper, inode);
+        Ok(())
+    }
+
+    pub(crate) fn write_at_in(&self, tree: Tree<'_>, path: FPath, offset: usize, data: Data) -> Result<(), CowFsError> {
+        if offset.checked_add(data.data.len()).is_none() {
+            Err(FileSystemError::FileTooLarge(path.clone()))?
+        }
+        let stepper = self.walk_file(tree.root(self), path)?;
+        let mut inode = stepper.inode();
+        inode.children = self.write_range(inode.children, offset, &data.data);
+        self.replace_file(tree, stepper, inode);
+        Ok(())
+    }
+
+    pub(crate) fn truncate_in(&self, tree: Tree<'_>, path: FPath, len: usize) -> Result<(), CowFsError> {
+        let stepper = self.walk_file(tree.root(self), path)?;
+        let mut inode = stepper.inode();
+        inode.children = self.truncate_range(inode.children, len);
+        self.replace_file(tree, stepper, inode);
+        Ok(())
+    }
+
+    pub(crate) fn append_in(&self, tree: Tree<'_>, path: FPath, data: Data) -> Result<(), CowFsError> {
+        let stepper = self.walk_file(tree.root(self), path)?;
+        let mut inode = stepper.inode();
+        let len = self.content_len(&inode.children);
+        inode.children = self.write_range(inode.children, len, &data.data);
+        self.replace_file(tree, stepper, inode);
+        Ok(())
+    }
+
+    pub(crate) fn create_dir_in(&self, tree: Tree<'_>, path: FPath) -> Result<(), CowFsError> {
+        self.create_node(tree, path, BlockType::Dir)
+    }
+
+    pub(crate) fn create_link_in(&self, tree: Tree<'_>, path: FPath, target: FPath) -> Result<(), CowFsError> {
+        // hard links to directories would turn the tree into a graph; a link
+        // to a symbolic link names the symbolic link itself
+        let target = Stepper::walk(self, tree.root(self).into(), target.clone(), false)?;
+        if target.btype == BlockType::Dir {
+            Err(FileSystemError::OperateFileOnDir(target.fpath.clone()))?
+        }
+        let btype = target.btype;
+        let (parent, name) = self.walk_vacant(tree, path)?;
+        let parent_path = parent.physical(self);
+        drop(parent);
+        let mut inode = target.inode();
+        inode.ref_cnt += 1;
+        let old = target.current;
+        drop(target);
+        let new = self.fresh(Block::INode(inode));
+        // Both the link count fix and the new entry are made on detached roots
+        // so that the tree changes with a single `install`.
+        let root = tree.root(self);
+        let root = self.relink_dir(root, old, new).unwrap_or(root);
+        // relinking left every directory on the physical path in place
+        let parent = Stepper::walk(self, Root::At(root), parent_path, false)?;
+        let dir = self.insert_entry(
+            parent.inode(),
+            DirEntry {
+                name,
+                btype,
+                inode: new,
+            },
+        );
+        let root = parent.shadow_detached(self, dir);
+        self.install(tree, root);
+        Ok(())
+    }
+
+    pub(crate) fn create_symlink_in(&self, tree: Tree<'_>, path: FPath, target: String) -> Result<(), CowFsError> {
+        let (stepper, name) = self.walk_vacant(tree, path)?;
+        let children = self.write_range(vec![], 0, target.as_bytes());
+        let inode = self.fresh(Block::INode(INode { ref_cnt: 1, children }));
+        let btype = BlockType::Symlink;
+        let dir = self.insert_entry(stepper.inode(), DirEntry { name, btype, inode });
+        stepper.shadow(self, tree, dir);
+        Ok(())
+    }
+
+    pub(crate) fn rename_in(&self, tree: Tree<'_>, from: FPath, to: FPath) -> Result<(), CowFsError> {
+        let (from_parent, from_name) = from.clone().parent().ok_or(FileSystemError::OperateOnRoot)?;
+        let (to_parent, to_name) = to.clone().parent().ok_or(FileSystemError::OperateOnRoot)?;
+        let src = self.walk_dir(tree.root(self), from_parent)?;
+        let (entries_id, slot, mut entry) = self
+            .find_entry(src.current, &from_name)
+            .ok_or(FileSystemError::FileNotInDir(from.clone()))?;
+        let dst = self.walk_dir(tree.root(self), to_parent)?;
+        let ancestors = dst.trail.iter().map(|step| step.dir).chain([dst.current]);
+        if entry.btype == BlockType::Dir && ancestors.clone().any(|dir| dir == entry.inode) {
+            Err(FileSystemError::MoveIntoSubtree(to.clone()))?
+        }
+        let existing = self.find_entry(dst.current, &to_name).map(|(_, _, existing)| existing);
+        if let Some(existing) = &existing {
+            if existing.inode == entry.inode {
+                // both names already lead to the same node
+                return Ok(());
+            }
+            match (entry.btype, existing.btype) {
+                (BlockType::Dir, BlockType::Dir) if !self.entries(existing.inode).is_empty() => {
+                    Err(FileSystemError::RemoveNonEmptyDir(to.clone()))?
+                }
+                (BlockType::Dir, BlockType::Dir) => {}
+                (BlockType::Dir, _) => Err(FileSystemError::OperateDirOnFile(to.clone()))?,
+                (_, BlockType::Dir) => Err(FileSystemError::OperateFileOnDir(to.clone()))?,
+                _ => {}
+            }
+        }
+        // Both edits, and the link count fix of a replaced file, are made on
+        // detached roots so that the tree changes with a single `set_root`.
+        let dst_path = dst.physical(self);
+        drop(dst);
+        let dir = self.remove_entry(src.inode(), entries_id, slot);
+        let root = src.shadow_detached(self, dir);
+        // the removal left every directory on the physical path in place
+        let dst = Stepper::walk(self, Root::At(root), dst_path, false)?;
+        let mut dir = dst.inode();
+        if let Some((entries_id, slot, _)) = self.find_entry(dst.current, &to_name) {
+            dir = self.remove_entry(dir, entries_id, slot);
+        }
+        entry.name = to_name;
+        let dir = self.insert_entry(dir, entry);
+        let mut root = dst.shadow_detached(self, dir);
+        if let Some(existing) = existing {
+            let mut inode = self.inode(existing.inode);
+            if inode.ref_cnt > 1 {
+                inode.ref_cnt -= 1;
+                let new = self.fresh(Block::INode(inode));
+                root = self.relink_dir(root, existing.inode, new).unwrap_or(root);
+            }
+        }
+        self.install(tree, root);
+        Ok(())
+    }
+
+    pub(crate) fn remove_in(&self, tree: Tree<'_>, path: FPath) -> Result<(), CowFsError> {
+        let (parent, name) = path.clone().parent().ok_or(FileSystemError::OperateOnRoot)?;
+        let parent = self.walk_dir(tree.root(self), parent)?;
+        let (entries_id, slot, entry) = self
+            .find_entry(parent.current, &name)
+            .ok_or(FileSystemError::FileNotInDir(path.clone()))?;
+        let mut inode = self.inode(entry.inode);
+        if entry.btype == BlockType::Dir && !self.entries(entry.inode).is_empty() {
+            Err(FileSystemError::RemoveNonEmptyDir(path))?
+        }
+        // the link count fix is made on the detached root of the removal, so
+        // that the tree changes with a single `install`
+        let dir = self.remove_entry(parent.inode(), entries_id, slot);
+        let mut root = parent.shadow_detached(self, dir);
+        if inode.ref_cnt > 1 {
+            inode.ref_cnt -= 1;
+            let new = self.fresh(Block::INode(inode));
+            root = self.relink_dir(root, entry.inode, new).unwrap_or(root);
+        }
+        self.install(tree, root);
+        Ok(())
+    }
+}
+
+impl<'fs> IFileSystem<'fs> for FileSys {
+    fn init() -> Self {
+        Self::fs_disk_init_with(PathBuf::new(), FileSys::fs_new_tables(), FileSys::fs_new_blocks())
+            .expect("in-memory init cannot fail")
+    }
+
+    fn create_file(&mut self, path: Self::Path<'fs>) -> Result<(), CowFsError> {
+        FileSys::create_file(self, path)
+    }
+
+    fn write_file(&mut self, path: Self::Path<'fs>, data: Self::Data) -> Result<(), CowFsError> {
+        FileSys::write_file(self, path, data)
+    }
+
+    fn write_at(&mut self, path: Self::Path<'fs>, offset: usize, data: Self::Data) -> Result<(), CowFsError> {
+        FileSys::write_at(self, path, offset, data)
+    }
+
+    fn truncate(&mut self, path: Self::Path<'fs>, len: usize) -> Result<(), CowFsError> {
+        FileSys::truncate(self, path, len)
+    }
+
+    fn append(&mut self, path: Self::Path<'fs>, data: Self::Data) -> Result<(), CowFsError> {
+        FileSys::append(self, path, data)
+    }
+
+    fn create_dir(&mut self, path: Self::Path<'fs>) -> Result<(), CowFsError> {
+        FileSys::create_dir(self, path)
+    }
+
+    fn create_link(&mut self, path: Self::Path<'fs>, target: Self::Path<'fs>) -> Result<(), CowFsError> {
+        FileSys::create_link(self, path, target)
+    }
+
+    fn create_symlink(&mut self, path: Self::Path<'fs>, target: String) -> Result<(), CowFsError> {
+        FileSys::create_symlink(self, path, target)
+    }
+
+    fn rename(&mut self, from: Self::Path<'fs>, to: Self::Path<'fs>) -> Result<(), CowFsError> {
+        FileSys::rename(self, from, to)
+    }
+
+    fn remove(&mut self, path: Self::Path<'fs>) -> Result<(), CowFsError> {
+        FileSys::remove(self, path)
+    }
+}
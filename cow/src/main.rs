@@ -1,11 +1,292 @@
-use cowffs::FileSys;
+use cowffs::{block::Data, view::FPath, FileSys, IMeta, IReadFileSystem};
+use std::{
+    io::{Read, Write},
+    path::PathBuf,
+};
 
-fn main() {
-    let mut args = std::env::args();
-    args.next();
-    let Some(fsys_path) = args.next() else {
-        return;
+const USAGE: &str = "\
+usage: cowffs [--confirm] <image> <command> [args...]
+
+commands:
+    mkfs [options]            create an empty image, with the options
+        --dedup               deduplicate identical data blocks
+        --compress <algo>     compress blocks with raw, lz4 or deflate
+    ls [path]                 list a directory
+    cat <path>                print a file to stdout
+    put <path> [host-file]    write a file from host-file, or from stdin
+    truncate <path> <len>     cut a file down, or extend it with zeros, to len bytes
+    mkdir <path>              create a directory
+    rm <path>                 remove a file or an empty directory
+    mv <from> <to>            rename or move an entry, replacing a file at to
+    ln <target> <path>        create a hard link at path to target
+    ln -s <target> <path>     create a symbolic link at path to target
+    stat <path>               show metadata
+    tree [path]               print a directory tree
+    snapshot [name]           take a snapshot, or list them without a name
+    diff <from> <to>          compare two snapshots or branches
+    dedup [on|off]            switch data deduplication, or show what it saves
+    scrub                     verify the whole image against its checksums
+    fsck [--repair]           check the structure of the image, and fix it with --repair
+
+--confirm asks before the image is rewritten.";
+
+fn path(raw: Option<String>) -> anyhow::Result<FPath> {
+    let raw = raw.ok_or_else(|| anyhow::anyhow!("missing path\n\n{}", USAGE))?;
+    Ok(FPath::new(&raw)?)
+}
+
+fn path_or_root(raw: Option<String>) -> anyhow::Result<FPath> {
+    match raw {
+        Some(raw) => Ok(FPath::new(&raw)?),
+        None => Ok(FPath::root()),
+    }
+}
+
+fn dump(fsys: &FileSys, confirm: bool) -> anyhow::Result<()> {
+    if confirm {
+        print!(
+            "Instance located at `{}` will be rewritten. Proceed? [../^C]",
+            fsys.instance.display()
+        );
+        std::io::stdout().flush()?;
+        std::io::stdin().read_line(&mut String::new())?;
+    }
+    let report = fsys.fs_disk_dump()?;
+    if fsys.dedup_enabled() {
+        eprintln!("dedup: {} bytes saved", report.saved());
+    }
+    Ok(())
+}
+
+/// How `ls` and `tree` show the entry `path`: a trailing `/` for a directory
+/// and the target for a symbolic link.
+fn label(fsys: &FileSys, path: &FPath, name: &str) -> anyhow::Result<String> {
+    let meta = fsys.metadata(path.clone())?;
+    Ok(if meta.is_symlink() {
+        format!("{} -> {}", name, fsys.read_link(path.clone())?)
+    } else if meta.is_dir() {
+        format!("{}/", name)
+    } else {
+        name.to_owned()
+    })
+}
+
+fn tree(fsys: &FileSys, path: FPath, depth: usize) -> anyhow::Result<()> {
+    let mut names = fsys.read_dir(path.clone())?;
+    names.sort();
+    for name in names {
+        let child = path.join(&name);
+        println!("{}{}", "    ".repeat(depth), label(fsys, &child, &name)?);
+        if fsys.metadata(child.clone())?.is_dir() {
+            tree(fsys, child, depth + 1)?;
+        }
+    }
+    Ok(())
+}
+
+fn main() -> anyhow::Result<()> {
+    let mut args = std::env::args().skip(1).peekable();
+    let confirm = args.next_if(|arg| arg == "--confirm").is_some();
+    let (Some(image), Some(command)) = (args.next(), args.next()) else {
+        eprintln!("{}", USAGE);
+        std::process::exit(2);
     };
-    let fsys = FileSys::fs_disk_load(fsys_path.into()).unwrap();
-    fsys.fs_disk_dump().unwrap();
+    let image = PathBuf::from(image);
+    if command == "mkfs" {
+        if image.exists() {
+            anyhow::bail!("`{}` already exists", image.display())
+        }
+        let mut fsys = FileSys::fs_disk_init(image)?;
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--dedup" => fsys.set_dedup(true),
+                "--compress" => {
+                    let algo = args
+                        .next()
+                        .ok_or_else(|| anyhow::anyhow!("missing algorithm\n\n{}", USAGE))?;
+                    fsys.compression = algo.parse()?;
+                }
+                _ => anyhow::bail!("unknown mkfs option `{}`\n\n{}", arg, USAGE),
+            }
+        }
+        return dump(&fsys, confirm);
+    }
+    if command == "fsck" {
+        let repair = match args.next().as_deref() {
+            None => false,
+            Some("--repair") => true,
+            Some(arg) => anyhow::bail!("unknown fsck option `{}`\n\n{}", arg, USAGE),
+        };
+        // checks the image as it is on disk, loading it could fail or paper over problems
+        let fsck = FileSys::fsck(image)?;
+        for problem in &fsck.problems {
+            println!("{}", problem);
+        }
+        if fsck.problems.is_empty() {
+            return Ok(());
+        }
+        if repair {
+            dump(&fsck.repaired, confirm)?;
+            eprintln!("fsck: {} problems repaired", fsck.problems.len());
+        } else {
+            eprintln!(
+                "fsck: {} problems, run `fsck --repair` to fix them",
+                fsck.problems.len()
+            );
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+    let mut fsys = FileSys::fs_disk_load(image)?;
+    let corrupt = fsys.corrupt_blocks();
+    if !corrupt.is_empty() {
+        eprintln!("warning: {} corrupt blocks, run `scrub` for details", corrupt.len());
+    }
+    match command.as_str() {
+        "ls" => {
+            let path = path_or_root(args.next())?;
+            let mut names = fsys.read_dir(path.clone())?;
+            names.sort();
+            for name in names {
+                println!("{}", label(&fsys, &path.join(&name), &name)?);
+            }
+        }
+        "cat" => {
+            let data = fsys.read_file(path(args.next())?)?;
+            std::io::stdout().write_all(&data.data)?;
+        }
+        "put" => {
+            let path = path(args.next())?;
+            let mut data = Vec::new();
+            match args.next() {
+                Some(host) => data = std::fs::read(host)?,
+                None => {
+                    std::io::stdin().read_to_end(&mut data)?;
+                }
+            }
+            if fsys.metadata(path.clone()).is_err() {
+                fsys.create_file(path.clone())?;
+            }
+            fsys.write_file(path, Data { data })?;
+            dump(&fsys, confirm)?;
+        }
+        "truncate" => {
+            let path = path(args.next())?;
+            let len = args
+                .next()
+                .ok_or_else(|| anyhow::anyhow!("missing length\n\n{}", USAGE))?;
+            fsys.truncate(path, len.parse()?)?;
+            dump(&fsys, confirm)?;
+        }
+        "mkdir" => {
+            fsys.create_dir(path(args.next())?)?;
+            dump(&fsys, confirm)?;
+        }
+        "rm" => {
+            fsys.remove(path(args.next())?)?;
+            dump(&fsys, confirm)?;
+        }
+        "mv" => {
+            let from = path(args.next())?;
+            fsys.rename(from, path(args.next())?)?;
+            dump(&fsys, confirm)?;
+        }
+        "ln" if args.peek().is_some_and(|arg| arg == "-s") => {
+            args.next();
+            let target = args
+                .next()
+                .ok_or_else(|| anyhow::anyhow!("missing target\n\n{}", USAGE))?;
+            fsys.create_symlink(path(args.next())?, target)?;
+            dump(&fsys, confirm)?;
+        }
+        "ln" => {
+            let target = path(args.next())?;
+            fsys.create_link(path(args.next())?, target)?;
+            dump(&fsys, confirm)?;
+        }
+        "stat" => {
+            let path = path(args.next())?;
+            let meta = fsys.metadata(path.clone())?;
+            println!("path:  {}", path);
+            let btype = if meta.is_symlink() {
+                "symbolic link"
+            } else if meta.is_dir() {
+                "directory"
+            } else {
+                "file"
+            };
+            println!("type:  {}", btype);
+            println!("inode: {}", meta.inode);
+            println!("links: {}", meta.nlink());
+            if meta.is_symlink() {
+                println!("target: {}", fsys.read_link(path)?);
+            } else if meta.is_file() {
+                println!("size:  {}", fsys.read_file(path)?.data.len());
+            } else {
+                println!("items: {}", fsys.read_dir(path)?.len());
+            }
+        }
+        "tree" => {
+            let path = path_or_root(args.next())?;
+            println!("{}", path);
+            tree(&fsys, path, 1)?;
+        }
+        "snapshot" => match args.next() {
+            Some(name) => {
+                fsys.snapshot(name)?;
+                dump(&fsys, confirm)?;
+            }
+            None => {
+                for (name, root) in fsys.list_snapshots() {
+                    println!("{}\t{}", name, root);
+                }
+            }
+        },
+        "diff" => {
+            let root = |name: Option<String>| {
+                let name = name.ok_or_else(|| anyhow::anyhow!("missing snapshot or branch\n\n{}", USAGE))?;
+                fsys.named_root(&name)
+                    .ok_or_else(|| anyhow::anyhow!("no snapshot or branch named `{}`", name))
+            };
+            let from = root(args.next())?;
+            let to = root(args.next())?;
+            for change in fsys.diff(from, to) {
+                println!("{}", change);
+            }
+        }
+        "dedup" => match args.next().as_deref() {
+            Some("on") => {
+                fsys.set_dedup(true);
+                dump(&fsys, confirm)?;
+            }
+            Some("off") => {
+                fsys.set_dedup(false);
+                dump(&fsys, confirm)?;
+            }
+            Some(arg) => anyhow::bail!("expected `on` or `off`, got `{}`", arg),
+            None => {
+                let report = fsys.space_report();
+                println!("dedup: {}", if fsys.dedup_enabled() { "on" } else { "off" });
+                println!("data blocks: {}", report.data_blocks);
+                println!("stored:      {} bytes", report.stored);
+                println!("referenced:  {} bytes", report.referenced);
+                println!("saved:       {} bytes", report.saved());
+            }
+        },
+        "scrub" => {
+            let bad = fsys.scrub()?;
+            for bad in &bad {
+                println!("{}\t{}\t{}\t{}", bad.id, bad.tree, bad.path, bad.reason);
+            }
+            if !bad.is_empty() {
+                eprintln!("scrub: {} bad blocks", bad.len());
+                std::process::exit(1);
+            }
+        }
+        _ => {
+            eprintln!("unknown command `{}`\n\n{}", command, USAGE);
+            std::process::exit(2);
+        }
+    }
+    Ok(())
 }
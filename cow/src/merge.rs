@@ -0,0 +1,143 @@
+use crate::{
+    block::{Block, BlockId, BlockType, DirEntry},
+    view::FPath,
+    FileSys,
+};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Display,
+};
+
+/// Changes that overlap with changes made to the same tree since both
+/// started out from a common root, by path.
+#[derive(Clone, Debug)]
+pub struct Conflict {
+    pub paths: Vec<FPath>,
+}
+
+impl Display for Conflict {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "conflicting changes to ")?;
+        for (idx, path) in self.paths.iter().enumerate() {
+            if idx > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "`{}`", path)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Conflict {}
+
+impl FileSys {
+    /// Merge the changes made from `base` to `ours` into `theirs`, another
+    /// tree that grew out of `base`. An entry that changed on one side only
+    /// takes that side; a directory that changed on both sides is merged
+    /// entry by entry. Anything else that changed on both sides is a conflict,
+    /// unless both made it a node of the same type and content. Returns the
+    /// root of the merged tree, whose blocks are pending until it gets
+    /// installed.
+    pub fn merge(&self, base: BlockId, ours: BlockId, theirs: BlockId) -> Result<BlockId, Conflict> {
+        let mut paths = Vec::new();
+        let root = self.merge_dir(&FPath::root(), Some(base), ours, theirs, &mut paths);
+        match paths.is_empty() {
+            true => Ok(root),
+            false => Err(Conflict { paths }),
+        }
+    }
+    fn merge_dir(
+        &self, path: &FPath, base: Option<BlockId>, ours: BlockId, theirs: BlockId, conflicts: &mut Vec<FPath>,
+    ) -> BlockId {
+        // subtrees that are the same block on two sides are not read
+        if base == Some(ours) || ours == theirs {
+            return theirs;
+        }
+        if base == Some(theirs) {
+            return ours;
+        }
+        let by_name = |dir: Option<BlockId>| {
+            dir.map(|dir| self.entries(dir))
+                .unwrap_or_default()
+                .into_iter()
+                .map(|entry| (entry.name.clone(), entry))
+                .collect::<BTreeMap<_, _>>()
+        };
+        let (base, ours, theirs_entries) = (by_name(base), by_name(Some(ours)), by_name(Some(theirs)));
+        let names = base
+            .keys()
+            .chain(ours.keys())
+            .chain(theirs_entries.keys())
+            .collect::<BTreeSet<_>>();
+        let same = |a: Option<&DirEntry>, b: Option<&DirEntry>| match (a, b) {
+            (Some(a), Some(b)) => a.btype == b.btype && a.inode == b.inode,
+            (a, b) => a.is_none() && b.is_none(),
+        };
+        let mut edits = BTreeMap::new();
+        for name in names {
+            let (base, ours, theirs) = (base.get(name), ours.get(name), theirs_entries.get(name));
+            if same(ours, theirs) || same(ours, base) {
+                continue;
+            }
+            if same(theirs, base) {
+                edits.insert(name.clone(), ours.cloned());
+                continue;
+            }
+            let path = path.join(name);
+            match (ours, theirs) {
+                (Some(ours), Some(theirs)) if ours.btype == BlockType::Dir && theirs.btype == BlockType::Dir => {
+                    let base = base.filter(|base| base.btype == BlockType::Dir);
+                    let merged =
+                        self.merge_dir(&path, base.map(|base| base.inode), ours.inode, theirs.inode, conflicts);
+                    if merged != theirs.inode {
+                        let entry = DirEntry {
+                            inode: merged,
+                            ..theirs.clone()
+                        };
+                        edits.insert(name.clone(), Some(entry));
+                    }
+                }
+                (Some(ours), Some(theirs))
+                    if ours.btype == theirs.btype && self.same_node(ours.inode, theirs.inode) => {}
+                _ => conflicts.push(path),
+            }
+        }
+        match edits.is_empty() {
+            true => theirs,
+            false => self.edit_dir(theirs, edits),
+        }
+    }
+    /// Whether two file or symbolic link `INode`s are alike in all but the
+    /// block they are stored in.
+    fn same_node(&self, a: BlockId, b: BlockId) -> bool {
+        self.inode(a).ref_cnt == self.inode(b).ref_cnt && self.same_content(a, b)
+    }
+    /// A copy of the directory `dir` in which each entry named in `edits` is
+    /// replaced by what `edits` holds for it, or removed for `None`; edits of
+    /// names `dir` does not have add entries.
+    fn edit_dir(&self, dir: BlockId, mut edits: BTreeMap<String, Option<DirEntry>>) -> BlockId {
+        let mut inode = self.inode(dir);
+        let mut children = Vec::with_capacity(inode.children.len());
+        for entries_id in inode.children {
+            let mut entries = self.dir_entries(entries_id);
+            let mut changed = false;
+            for slot in entries.iter_mut() {
+                if let Some(edit) = slot.as_ref().and_then(|entry| edits.remove(&entry.name)) {
+                    *slot = edit;
+                    changed = true;
+                }
+            }
+            match changed {
+                false => children.push(entries_id),
+                // a block left with no live entry is dropped, as by `remove_entry`
+                true if entries.iter().all(Option::is_none) => {}
+                true => children.push(self.fresh(Block::DirEntries(entries))),
+            }
+        }
+        inode.children = children;
+        for entry in edits.into_values().flatten() {
+            inode = self.insert_entry(inode, entry);
+        }
+        self.fresh(Block::INode(inode))
+    }
+}
@@ -0,0 +1,80 @@
+use crate::{
+    block::{BlockId, Data},
+    view::FPath,
+    CowFsError, FileSys, IReadFileSystem, Meta,
+};
+
+/* --------------------------------- readers -------------------------------- */
+
+/// Read-only access to the tree of the checked-out branch as it was when the
+/// reader was taken, see `FileSys::reader`.
+///
+/// The reader pins its root, which keeps every block below it from being
+/// reclaimed, and as no block in use is ever changed, it reads them while
+/// writers build newer trees next to them. Its walks skip the lock on the
+/// root of the branch, and the block locks they take are shared ones no
+/// writer ever waits for, so readers and writers never hold each other up.
+pub struct Reader<'fs> {
+    fs: &'fs FileSys,
+    root: BlockId,
+}
+
+impl FileSys {
+    /// Pin the current root of the checked-out branch for reading.
+    pub fn reader(&self) -> Reader<'_> {
+        // keep the root from being swapped and released before it is pinned
+        let head = self.root.read().unwrap();
+        self.pin(*head);
+        Reader { fs: self, root: *head }
+    }
+}
+
+impl Reader<'_> {
+    /// The root the reader is pinned to.
+    pub fn root(&self) -> BlockId {
+        self.root
+    }
+}
+
+impl Drop for Reader<'_> {
+    fn drop(&mut self) {
+        // Reclaiming blocks takes `writer` exclusively, since an operation
+        // holding it may be about to reuse a block this would free, as
+        // deduplication does; if it is taken, the next `settle` drops the pin.
+        match self.fs.writer.try_write() {
+            Ok(_writer) => {
+                self.fs.unpin(self.root);
+                self.fs.settle();
+            }
+            Err(_) => self.fs.unpin_later(self.root),
+        }
+    }
+}
+
+impl<'fs> IReadFileSystem<'fs> for Reader<'_> {
+    type Path<'p> = FPath;
+
+    type Meta = Meta;
+
+    type Data = Data;
+
+    fn metadata(&self, path: Self::Path<'fs>) -> Result<Self::Meta, CowFsError> {
+        self.fs.metadata_at(self.root, path)
+    }
+
+    fn read_file(&self, path: Self::Path<'fs>) -> Result<Self::Data, CowFsError> {
+        self.fs.read_file_at(self.root, path)
+    }
+
+    fn read_at(&self, path: Self::Path<'fs>, offset: usize, len: usize) -> Result<Self::Data, CowFsError> {
+        self.fs.read_range_at(self.root, path, offset, len)
+    }
+
+    fn read_dir(&self, path: Self::Path<'fs>) -> Result<Vec<String>, CowFsError> {
+        self.fs.read_dir_at(self.root, path)
+    }
+
+    fn read_link(&self, path: Self::Path<'fs>) -> Result<String, CowFsError> {
+        self.fs.read_link_at(self.root, path)
+    }
+}
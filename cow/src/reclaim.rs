@@ -0,0 +1,246 @@
+use crate::{
+    block::{Block, BlockId},
+    Books, FileSys, Tree,
+};
+use std::{collections::BTreeSet, sync::MutexGuard};
+
+/// Outcome of a mark-and-sweep pass.
+#[derive(Clone, Default, Debug)]
+pub struct GcReport {
+    /// Blocks that were unreachable from every root but not yet freed.
+    pub freed: Vec<BlockId>,
+    /// Blocks whose maintained reference count disagreed with the number of
+    /// references actually found, as `(block, maintained, found)`.
+    pub mismatches: Vec<(BlockId, usize, usize)>,
+    /// Slots in use that the allocator had down as free.
+    pub unmarked: Vec<BlockId>,
+    /// Slots the allocator had down as used that nothing reaches, including
+    /// those of `freed`.
+    pub leaked: Vec<BlockId>,
+}
+
+/* ---------------------------- reference counts ---------------------------- */
+
+impl FileSys {
+    pub(crate) fn books(&self) -> MutexGuard<'_, Books> {
+        self.books.lock().unwrap()
+    }
+    /// Every root that keeps a tree alive: the checked-out branch, the other
+    /// branches, the snapshots and the roots pinned by readers and open
+    /// transactions. A root held twice counts twice.
+    pub fn root_holders(&self) -> Vec<BlockId> {
+        let mut roots = vec![self.root()];
+        roots.extend(self.branches.values().copied());
+        roots.extend(self.snapshots.values().copied());
+        roots.extend(self.books().pins.iter().copied());
+        roots
+    }
+    pub(crate) fn retain(&self, id: BlockId) {
+        self.books().refs[id.0] += 1;
+    }
+    /// Take a reference to `root` on behalf of a handle that works on the
+    /// tree below it, which keeps the tree from being reclaimed.
+    pub(crate) fn pin(&self, root: BlockId) {
+        let mut books = self.books();
+        books.refs[root.0] += 1;
+        books.pins.push(root);
+    }
+    /// Drop a pin on `root`. Reclaims blocks, so only to be called with
+    /// `writer` held exclusively, see `unpin_later`.
+    pub(crate) fn unpin(&self, root: BlockId) {
+        let mut books = self.books();
+        let pin = books.pins.iter().position(|&pin| pin == root).expect("root is pinned");
+        books.pins.swap_remove(pin);
+        self.release_in(&mut books, root);
+    }
+    /// Leave a pin on `root` for the next `settle` to drop.
+    pub(crate) fn unpin_later(&self, root: BlockId) {
+        self.books().unpinned.push(root);
+    }
+    /// Drop one reference to `id`, freeing it and, transitively, whatever it
+    /// held on to once nothing refers to it anymore.
+    pub(crate) fn release(&self, id: BlockId) {
+        self.release_in(&mut self.books(), id);
+    }
+    fn release_in(&self, books: &mut Books, id: BlockId) {
+        let mut stack = vec![id];
+        while let Some(id) = stack.pop() {
+            books.refs[id.0] -= 1;
+            if books.refs[id.0] == 0 {
+                stack.extend(self.free(books, id));
+            }
+        }
+    }
+    /// Turn `id` into a `Block::Free` and return its slot to the allocator,
+    /// handing back the references it held. A block that a walk still holds
+    /// is left for a later `settle` instead.
+    fn free(&self, books: &mut Books, id: BlockId) -> Vec<BlockId> {
+        let Ok(mut guard) = self[id].try_write() else {
+            books.deferred.push(id);
+            return vec![];
+        };
+        let block = std::mem::replace(&mut *guard, Block::Free);
+        drop(guard);
+        books.alloc.release(id);
+        books.dirty.insert(id);
+        if self.corrupt.read().unwrap().contains_key(&id) {
+            self.corrupt.write().unwrap().remove(&id);
+        }
+        if let (Block::Data(data), Some(index)) = (&block, &mut books.dedup) {
+            index.forget(id, data);
+        }
+        block.children()
+    }
+    /// Make `root` the root of the checked-out branch.
+    pub(crate) fn set_root(&self, root: BlockId) {
+        self.retain(root);
+        let old = std::mem::replace(&mut *self.root.write().unwrap(), root);
+        self.release(old);
+        self.settle();
+    }
+    /// Make `root` the root of `tree`.
+    pub(crate) fn install(&self, tree: Tree<'_>, root: BlockId) {
+        match tree {
+            Tree::Head => self.set_root(root),
+            // reclaiming is left to whoever holds `writer` exclusively
+            Tree::Work(work) => {
+                self.pin(root);
+                self.unpin_later(work.replace(root));
+            }
+        }
+    }
+    /// Free blocks that were allocated but never got linked into any tree,
+    /// and those a walk held when they were to be freed, and drop the pins
+    /// left for later. Only to be called with `writer` held exclusively.
+    pub(crate) fn settle(&self) {
+        let unpinned = std::mem::take(&mut self.books().unpinned);
+        for root in unpinned {
+            self.unpin(root);
+        }
+        let mut books = self.books();
+        let pending = std::mem::take(&mut books.pending);
+        let deferred = std::mem::take(&mut books.deferred);
+        for id in pending.into_iter().chain(deferred) {
+            // a deferred block may have been handed out again by deduplication
+            if books.refs[id.0] == 0 && books.alloc.is_used(id) {
+                for child in self.free(&mut books, id) {
+                    self.release_in(&mut books, child);
+                }
+            }
+        }
+    }
+}
+
+/* ------------------------------ mark & sweep ------------------------------ */
+
+impl FileSys {
+    /// Count the references to every block by walking all trees from their
+    /// roots; blocks that cannot be reached are reported as `None`.
+    fn count_refs(&self) -> Vec<Option<usize>> {
+        let mut counts = vec![None; self.blocks.len()];
+        let mut stack = Vec::new();
+        for root in self.root_holders() {
+            *counts[root.0].get_or_insert(0) += 1;
+            stack.push(root);
+        }
+        // a block whose free was deferred still holds on to its children
+        stack.extend(self.books().deferred.iter().copied());
+        let mut visited = vec![false; self.blocks.len()];
+        while let Some(id) = stack.pop() {
+            if std::mem::replace(&mut visited[id.0], true) {
+                continue;
+            }
+            for child in self[id].read().unwrap().children() {
+                *counts[child.0].get_or_insert(0) += 1;
+                stack.push(child);
+            }
+        }
+        counts
+    }
+    /// The reference counts of the image of the current state, which holds no
+    /// pins, along with the blocks that only pins and deferred frees keep,
+    /// which it leaves out.
+    pub(crate) fn image_refs(&self) -> (Vec<usize>, BTreeSet<BlockId>) {
+        let books = self.books();
+        let (mut refs, mut stack, deferred) = (books.refs.clone(), books.pins.clone(), books.deferred.clone());
+        drop(books);
+        let mut hidden = BTreeSet::new();
+        for id in deferred {
+            hidden.insert(id);
+            stack.extend(self[id].read().unwrap().children());
+        }
+        // release what the image does not hold, as `release_in` would
+        while let Some(id) = stack.pop() {
+            refs[id.0] -= 1;
+            if refs[id.0] == 0 && hidden.insert(id) {
+                stack.extend(self[id].read().unwrap().children());
+            }
+        }
+        (refs, hidden)
+    }
+    /// Compare the maintained reference counts against a full walk.
+    pub fn verify_refs(&self) -> Vec<(BlockId, usize, usize)> {
+        let counts = self.count_refs();
+        let books = self.books();
+        counts
+            .into_iter()
+            .enumerate()
+            .filter_map(|(idx, found)| {
+                let found = found.unwrap_or(0);
+                (books.refs[idx] != found).then_some((BlockId(idx), books.refs[idx], found))
+            })
+            .collect()
+    }
+    /// Mark every block reachable from a root, free the rest and reset the
+    /// reference counts to what was found. The allocator is checked against
+    /// the same: slots it disagrees on are reported and set right.
+    pub fn gc(&mut self) -> GcReport {
+        // nothing holds a block anymore, so deferred frees go through
+        self.settle();
+        let mut report = GcReport {
+            mismatches: self.verify_refs(),
+            ..GcReport::default()
+        };
+        let counts = self.count_refs();
+        // unreachable blocks of the loaded image need not be read in, as the
+        // references they hold are recounted here
+        for idx in (0..counts.len()).filter(|&idx| counts[idx].is_none()) {
+            self.skip_read(BlockId(idx));
+        }
+        let books = self.books.get_mut().unwrap();
+        books.alloc.resize(counts.len());
+        for (idx, found) in counts.into_iter().enumerate() {
+            let id = BlockId(idx);
+            match found {
+                Some(found) => {
+                    books.refs[idx] = found;
+                    if !books.alloc.is_used(id) {
+                        books.alloc.claim(id);
+                        report.unmarked.push(id);
+                    }
+                }
+                None => {
+                    books.refs[idx] = 0;
+                    books.dirty.insert(id);
+                    let mut block = self.blocks[id].write().unwrap();
+                    if !matches!(*block, Block::Free) {
+                        *block = Block::Free;
+                        report.freed.push(id);
+                    }
+                    if books.alloc.is_used(id) {
+                        books.alloc.release(id);
+                        report.leaked.push(id);
+                    }
+                }
+            }
+        }
+        books.pending.clear();
+        books.deferred.clear();
+        self.corrupt.get_mut().unwrap().retain(|id, _| books.refs[id.0] > 0);
+        if books.dedup.is_some() {
+            let index = self.build_dedup();
+            self.books.get_mut().unwrap().dedup = Some(index);
+        }
+        report
+    }
+}
@@ -0,0 +1,219 @@
+use crate::{
+    block::{Block, BlockId, BlockType, Data, INode},
+    disk::{ImageReader, Kind, Record, Tables},
+    view::FPath,
+    CowFsError, FileSys, FileSystemError,
+};
+
+/// A block of an image that does not match the checksum its parent holds
+/// for it, or that cannot be read at all.
+#[derive(Clone, Debug)]
+pub struct BadBlock {
+    pub id: BlockId,
+    /// The tree the block was reached in, such as `branch main`.
+    pub tree: String,
+    /// The path at or under which the block was reached.
+    pub path: FPath,
+    /// The checksum its parent holds for it.
+    pub expected: u32,
+    pub reason: String,
+}
+
+/// What the reference to a block says it should be.
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum Expect {
+    INode(BlockType),
+    DirEntries,
+    /// A `Data` or `Indirect` block of a file or symbolic link.
+    Content,
+}
+
+impl Expect {
+    /// What a block stored as `kind` is taken for when nothing refers to it
+    /// yet; `None` for a free block.
+    pub fn of(kind: Kind) -> Option<Self> {
+        match kind {
+            Kind::INode => Some(Expect::INode(BlockType::File)),
+            Kind::DirEntries => Some(Expect::DirEntries),
+            Kind::Data | Kind::Indirect => Some(Expect::Content),
+            Kind::Free => None,
+        }
+    }
+    pub fn admits(self, block: &Block) -> bool {
+        matches!(
+            (self, block),
+            (Expect::INode(_), Block::INode(_))
+                | (Expect::DirEntries, Block::DirEntries(_))
+                | (Expect::Content, Block::Data(_) | Block::Indirect(_))
+        )
+    }
+    /// The children of `block`, a block that matches `self`, along with what
+    /// each of them should be and, for those named by directory entries, the
+    /// name.
+    pub fn children(self, block: &Block) -> Vec<(BlockId, Expect, Option<&str>)> {
+        match (self, block) {
+            (Expect::INode(BlockType::Dir), Block::INode(inode)) => inode
+                .children
+                .iter()
+                .map(|&id| (id, Expect::DirEntries, None))
+                .collect(),
+            (_, Block::DirEntries(entries)) => entries
+                .iter()
+                .flatten()
+                .map(|entry| (entry.inode, Expect::INode(entry.btype), Some(entry.name.as_str())))
+                .collect(),
+            (_, block) => block
+                .children()
+                .into_iter()
+                .map(|id| (id, Expect::Content, None))
+                .collect(),
+        }
+    }
+}
+
+/// The trees an image holds, with a name for each.
+pub(crate) fn trees(tables: &Tables) -> Vec<(String, BlockId)> {
+    let mut trees = vec![(format!("branch {}", tables.head), tables.root)];
+    trees.extend(
+        tables
+            .branches
+            .iter()
+            .map(|(name, &root)| (format!("branch {}", name), root)),
+    );
+    trees.extend(
+        tables
+            .snapshots
+            .iter()
+            .map(|(name, &root)| (format!("snapshot {}", name), root)),
+    );
+    trees
+}
+
+/// Walk every tree of `tables` down `records`, checking each block against
+/// the checksum its parent holds for it. Blocks below a bad one are not
+/// visited; each bad block is reported once, with what it should have been.
+fn verify(records: &[anyhow::Result<Record>], tables: &Tables) -> Vec<(BadBlock, Expect)> {
+    let mut bad = Vec::new();
+    let mut visited = vec![false; records.len()];
+    for (tree, root) in trees(tables) {
+        let expected = tables.root_sums.get(&root).copied().unwrap_or_default();
+        let mut stack = vec![(root, expected, Expect::INode(BlockType::Dir), FPath::root())];
+        while let Some((id, expected, expect, path)) = stack.pop() {
+            let Some(record) = records.get(id.0) else {
+                let reason = "block out of range".to_owned();
+                bad.push((
+                    BadBlock {
+                        id,
+                        tree: tree.clone(),
+                        path,
+                        expected,
+                        reason,
+                    },
+                    expect,
+                ));
+                continue;
+            };
+            if std::mem::replace(&mut visited[id.0], true) {
+                continue;
+            }
+            let reason = match record {
+                Err(err) => err.to_string(),
+                Ok(record) if record.sum != expected => {
+                    format!("checksum mismatch: expected {:08x}, found {:08x}", expected, record.sum)
+                }
+                Ok(record) if !expect.admits(&record.block) => format!("expected {:?}", expect),
+                Ok(record) => {
+                    let children = expect.children(&record.block);
+                    for ((child, expect, name), &sum) in children.into_iter().zip(&record.child_sums) {
+                        let path = name.map_or_else(|| path.clone(), |name| path.join(name));
+                        stack.push((child, sum, expect, path));
+                    }
+                    continue;
+                }
+            };
+            bad.push((
+                BadBlock {
+                    id,
+                    tree: tree.clone(),
+                    path,
+                    expected,
+                    reason,
+                },
+                expect,
+            ));
+        }
+    }
+    bad
+}
+
+/// What stands in for a bad block once read: a `Data` block keeps the
+/// bytes it was read with, anything else is emptied, as its references
+/// cannot be trusted.
+pub(crate) fn stand_in(block: Option<Block>, expect: Expect) -> Block {
+    match block.filter(|block| expect.admits(block)) {
+        Some(Block::Data(data)) => Block::Data(data),
+        Some(Block::INode(inode)) => Block::INode(INode {
+            children: vec![],
+            ..inode
+        }),
+        Some(Block::Indirect(_)) => Block::Indirect(vec![]),
+        _ => match expect {
+            Expect::INode(_) => Block::INode(INode {
+                ref_cnt: 1,
+                children: vec![],
+            }),
+            Expect::DirEntries => Block::DirEntries(vec![]),
+            Expect::Content => Block::Data(Data::from(vec![])),
+        },
+    }
+}
+
+impl FileSys {
+    /// Verify every tree of the image on disk, which holds the state of the
+    /// last `fs_disk_dump`, against its checksums.
+    pub fn scrub(&self) -> anyhow::Result<Vec<BadBlock>> {
+        let mut reader = ImageReader::open(&self.instance)?;
+        let tables = reader.read_tables()?;
+        let records = reader.read_records();
+        Ok(verify(&records, &tables).into_iter().map(|(bad, _)| bad).collect())
+    }
+    /// Blocks of the loaded image found bad as they were read, and still in
+    /// use.
+    pub fn corrupt_blocks(&self) -> Vec<BlockId> {
+        self.corrupt.read().unwrap().keys().copied().collect()
+    }
+    /// Fail if `id` is a block of the loaded image found bad, reading it
+    /// first if that is still to happen. The error names the node the block
+    /// belongs to by the path `path` makes, only called on a bad block.
+    pub(crate) fn check(&self, id: BlockId, path: impl Fn() -> FPath) -> Result<(), CowFsError> {
+        self.read_in(id);
+        match self.corrupt.read().unwrap().contains_key(&id) {
+            true => Err(FileSystemError::CorruptBlock(path(), id.to_string())),
+            false => Ok(()),
+        }
+    }
+    /// Like `check`, for the directory `INode` `dir` and its `DirEntries`.
+    pub(crate) fn check_dir(&self, dir: BlockId, path: impl Fn() -> FPath) -> Result<(), CowFsError> {
+        if self.corrupt.read().unwrap().is_empty() && self.unread.is_done() {
+            return Ok(());
+        }
+        self.check(dir, &path)?;
+        for entries in self.inode(dir).children {
+            self.check(entries, &path)?;
+        }
+        Ok(())
+    }
+    /// Like `check`, for the `INode` `id` of a file or symbolic link and every
+    /// block of its content.
+    pub(crate) fn check_tree(&self, id: BlockId, path: impl Fn() -> FPath) -> Result<(), CowFsError> {
+        if self.corrupt.read().unwrap().is_empty() && self.unread.is_done() {
+            return Ok(());
+        }
+        let mut stack = vec![id];
+        while let Some(id) = stack.pop() {
+            self.check(id, &path)?;
+            stack.extend(self[id].read().unwrap().children());
+        }
+        Ok(())
+    }
+}
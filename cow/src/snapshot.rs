@@ -0,0 +1,97 @@
+use crate::{
+    block::{BlockId, Data},
+    view::FPath,
+    CowFsError, FileSys, IReadFileSystem, Meta,
+};
+
+/* ----------------------------- snapshot table ----------------------------- */
+
+impl FileSys {
+    /// Record the current root under `name`. Every block is shared with the
+    /// live tree; since mutations only ever shadow blocks, the snapshot stays
+    /// intact no matter what happens to the live tree afterwards.
+    pub fn snapshot(&mut self, name: impl Into<String>) -> anyhow::Result<BlockId> {
+        let name = name.into();
+        if self.snapshots.contains_key(&name) {
+            anyhow::bail!("snapshot `{}` already exists", name)
+        }
+        let root = self.root();
+        self.snapshots.insert(name, root);
+        self.retain(root);
+        Ok(root)
+    }
+    /// Names of all snapshots, in order, with their roots.
+    pub fn list_snapshots(&self) -> Vec<(String, BlockId)> {
+        self.snapshots
+            .iter()
+            .map(|(name, &root)| (name.clone(), root))
+            .collect()
+    }
+    /// Forget the snapshot `name`, freeing every block only it still used.
+    pub fn delete_snapshot(&mut self, name: &str) -> anyhow::Result<()> {
+        let root = self
+            .snapshots
+            .remove(name)
+            .ok_or_else(|| anyhow::anyhow!("snapshot `{}` not found", name))?;
+        self.release(root);
+        Ok(())
+    }
+    /// A read-only view of the tree as it was when `name` was taken.
+    pub fn open_snapshot(&self, name: &str) -> anyhow::Result<RootView<'_>> {
+        let root = *self
+            .snapshots
+            .get(name)
+            .ok_or_else(|| anyhow::anyhow!("snapshot `{}` not found", name))?;
+        Ok(self.view(root))
+    }
+    /// A read-only view of the tree rooted at the directory `INode` `root`,
+    /// which the caller keeps alive for as long as the view lasts.
+    pub(crate) fn view(&self, root: BlockId) -> RootView<'_> {
+        RootView { fs: self, root }
+    }
+}
+
+/* -------------------------------- root view ------------------------------- */
+
+/// Read-only access to the tree under a fixed root. A view borrows what
+/// keeps its root alive: the file system, whose snapshots cannot be deleted
+/// while it is borrowed, or the transaction it shows.
+pub struct RootView<'a> {
+    fs: &'a FileSys,
+    root: BlockId,
+}
+
+impl RootView<'_> {
+    /// The root of the tree the view shows.
+    pub fn root(&self) -> BlockId {
+        self.root
+    }
+}
+
+impl<'fs> IReadFileSystem<'fs> for RootView<'_> {
+    type Path<'p> = FPath;
+
+    type Meta = Meta;
+
+    type Data = Data;
+
+    fn metadata(&self, path: Self::Path<'fs>) -> Result<Self::Meta, CowFsError> {
+        self.fs.metadata_at(self.root, path)
+    }
+
+    fn read_file(&self, path: Self::Path<'fs>) -> Result<Self::Data, CowFsError> {
+        self.fs.read_file_at(self.root, path)
+    }
+
+    fn read_at(&self, path: Self::Path<'fs>, offset: usize, len: usize) -> Result<Self::Data, CowFsError> {
+        self.fs.read_range_at(self.root, path, offset, len)
+    }
+
+    fn read_dir(&self, path: Self::Path<'fs>) -> Result<Vec<String>, CowFsError> {
+        self.fs.read_dir_at(self.root, path)
+    }
+
+    fn read_link(&self, path: Self::Path<'fs>) -> Result<String, CowFsError> {
+        self.fs.read_link_at(self.root, path)
+    }
+}
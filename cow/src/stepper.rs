@@ -1,23 +1,197 @@
 use crate::{
-    block::{Block, BlockId},
-    view::{self, FPath},
-    FileSys,
+    block::{Block, BlockId, BlockType, INode},
+    view::FPath,
+    CowFsError, FileSys, FileSystemError, Tree, SYMLINK_HOPS_LIMIT,
 };
-use std::sync::{Arc, RwLock};
+use std::{collections::VecDeque, sync::RwLockReadGuard};
 
-pub struct Stepper<'fp> {
-    pub lock: Arc<RwLock<Block>>,
+/// One level of a walk: the directory `INode` we stepped out of, and the
+/// `DirEntries` block and slot whose entry led one level further down.
+#[derive(Clone, Debug)]
+pub struct Step {
+    pub dir: BlockId,
+    pub entries: BlockId,
+    pub slot: usize,
+}
+
+/// Walks an `FPath` from a root down to the node it names, remembering the
+/// trail so that a modified node can be shadowed all the way up to a new root.
+///
+/// Symbolic links met on the way are followed by splicing their target into
+/// the segments still to walk; the trail always records where the stepper
+/// physically stands.
+///
+/// The stepper reads the blocks on its way under their locks, taken hand over
+/// hand: the lock of the next node is taken before that of the current one is
+/// let go. It also holds the lock of its root for as long as it lives, which
+/// keeps the whole tree from being reclaimed under it, as `..` and absolute
+/// symbolic links go back up to nodes it no longer holds.
+pub struct Stepper<'fs> {
+    anchor: RwLockReadGuard<'fs, Block>,
+    guard: RwLockReadGuard<'fs, Block>,
+    pub root: BlockId,
     pub current: BlockId,
-    pub fp_iter: view::IntoIter<'fp>,
+    pub btype: BlockType,
+    pub fpath: FPath,
+    pub pending: VecDeque<String>,
+    /// whether a symbolic link named by the last segment is followed too
+    pub follow_last: bool,
+    pub hops: usize,
+    pub trail: Vec<Step>,
+}
+
+/// Where a walk starts.
+#[derive(Clone, Copy, Debug)]
+pub enum Root {
+    /// The root of the checked-out branch, whichever it is when the walk
+    /// starts.
+    Head,
+    /// A given root, which the caller keeps alive.
+    At(BlockId),
 }
 
-impl<'fp> Stepper<'fp> {
-    pub fn new(fs: &mut FileSys, fpath: FPath<'fp>) -> anyhow::Result<Self> {
-        let current = FileSys::root();
-        Ok(Stepper {
-            lock: fs[current].clone(),
+impl From<BlockId> for Root {
+    fn from(root: BlockId) -> Self {
+        Root::At(root)
+    }
+}
+
+impl<'fs> Stepper<'fs> {
+    pub fn new(fs: &'fs FileSys, root: Root, fpath: FPath, follow_last: bool) -> Self {
+        let (root, anchor) = match root {
+            Root::Head => {
+                // keep the root from being swapped until its block is locked
+                let head = fs.root.read().unwrap();
+                (*head, fs[*head].read().unwrap())
+            }
+            Root::At(root) => (root, fs[root].read().unwrap()),
+        };
+        let current = root;
+        Stepper {
+            anchor,
+            guard: fs[current].read().unwrap(),
+            root,
             current,
-            fp_iter: fpath.into_iter(),
-        })
+            btype: BlockType::Dir,
+            pending: fpath.0.iter().cloned().collect(),
+            fpath,
+            follow_last,
+            hops: 0,
+            trail: vec![],
+        }
+    }
+    /// Walk all the way down `fpath`, starting from the directory `root`.
+    pub fn walk(fs: &'fs FileSys, root: Root, fpath: FPath, follow_last: bool) -> Result<Self, CowFsError> {
+        let mut stepper = Stepper::new(fs, root, fpath, follow_last);
+        while stepper.step(fs)? {}
+        Ok(stepper)
+    }
+    /// Descend by one path segment, or follow the symbolic link the stepper
+    /// stands on; returns `false` once the path is exhausted.
+    pub fn step(&mut self, fs: &'fs FileSys) -> Result<bool, CowFsError> {
+        if self.btype == BlockType::Symlink && (self.follow_last || !self.pending.is_empty()) {
+            self.follow(fs)?;
+            return Ok(true);
+        }
+        let Some(segment) = self.pending.pop_front() else {
+            return Ok(false);
+        };
+        if self.btype != BlockType::Dir {
+            Err(FileSystemError::IndexOnFile(self.fpath.clone()))?
+        }
+        match segment.as_str() {
+            "" | "." => {}
+            ".." => {
+                // `..` of the root is the root itself
+                if let Some(step) = self.trail.pop() {
+                    self.move_to(fs, step.dir, BlockType::Dir);
+                }
+            }
+            _ => {
+                fs.check_dir(self.current, || self.physical(fs))?;
+                let (entries, slot, entry) = fs
+                    .find_entry(self.current, &segment)
+                    .ok_or(FileSystemError::FileNotInDir(self.fpath.clone()))?;
+                self.trail.push(Step {
+                    dir: self.current,
+                    entries,
+                    slot,
+                });
+                self.move_to(fs, entry.inode, entry.btype);
+            }
+        }
+        Ok(true)
+    }
+    /// Replace the symbolic link the stepper stands on by its target.
+    fn follow(&mut self, fs: &'fs FileSys) -> Result<(), CowFsError> {
+        self.hops += 1;
+        if self.hops > SYMLINK_HOPS_LIMIT {
+            Err(FileSystemError::SymlinkLoop(self.fpath.clone()))?
+        }
+        fs.check_tree(self.current, || self.physical(fs))?;
+        let target = fs.link_target(self.current);
+        let step = self.trail.pop().expect("a symbolic link is never the root");
+        if target.starts_with('/') {
+            self.trail.clear();
+            self.move_to(fs, self.root, BlockType::Dir);
+        } else {
+            self.move_to(fs, step.dir, BlockType::Dir);
+        }
+        for segment in target.split('/').rev() {
+            self.pending.push_front(segment.to_owned());
+        }
+        Ok(())
+    }
+    fn move_to(&mut self, fs: &'fs FileSys, id: BlockId, btype: BlockType) {
+        // the old guard is only dropped once the new one is in place
+        self.guard = fs[id].read().unwrap();
+        self.current = id;
+        self.btype = btype;
+    }
+    /// The `INode` the stepper currently stands on.
+    pub fn inode(&self) -> INode {
+        self.guard.clone().inode()
+    }
+    /// The path from the root to where the stepper stands, with every symbolic
+    /// link on the way already replaced by the directory it led to.
+    pub fn physical(&self, fs: &FileSys) -> FPath {
+        let names = self.trail.iter().map(|step| {
+            let entries = fs.dir_entries(step.entries);
+            entries[step.slot]
+                .as_ref()
+                .expect("trail points at a live entry")
+                .name
+                .clone()
+        });
+        FPath(names.collect())
+    }
+    /// Store `inode` as the new version of the current node, then shadow every
+    /// `DirEntries` and `INode` on the trail up to a new root, which becomes the
+    /// root of `tree`. None of the blocks on the old trail are modified.
+    pub(crate) fn shadow(self, fs: &FileSys, tree: Tree<'_>, inode: INode) -> BlockId {
+        let root = self.shadow_detached(fs, inode);
+        fs.install(tree, root);
+        root
+    }
+    /// Like `shadow`, but the new root is only returned, not installed. Blocks
+    /// of a detached root that never gets installed are reclaimed by the next
+    /// `install`.
+    pub fn shadow_detached(self, fs: &FileSys, inode: INode) -> BlockId {
+        // let go of the old tree, so that installing the new root can reclaim it
+        let Stepper { trail, .. } = self;
+        let mut child = fs.fresh(Block::INode(inode));
+        for step in trail.into_iter().rev() {
+            let mut entries = fs.dir_entries(step.entries);
+            entries[step.slot].as_mut().expect("trail points at a live entry").inode = child;
+            let entries = fs.fresh(Block::DirEntries(entries));
+            let mut dir = fs.inode(step.dir);
+            for id in dir.children.iter_mut() {
+                if *id == step.entries {
+                    *id = entries;
+                }
+            }
+            child = fs.fresh(Block::INode(dir));
+        }
+        child
     }
 }
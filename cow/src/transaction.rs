@@ -0,0 +1,127 @@
+use crate::{
+    block::{BlockId, Data},
+    merge::Conflict,
+    snapshot::RootView,
+    view::FPath,
+    CowFsError, FileSys, Tree,
+};
+use std::cell::Cell;
+
+/* ------------------------------ transactions ------------------------------ */
+
+/// A batch of operations that becomes visible all at once.
+///
+/// The transaction builds a tree of its own, starting out as that of the
+/// checked-out branch, and `commit` merges it into the tree the branch has
+/// by then with a single root swap; until then, readers keep seeing the tree
+/// as it was. Aborting, or dropping the transaction, releases its tree,
+/// which reclaims every block it wrote.
+///
+/// Transactions are optimistic: any number of them build their trees at the
+/// same time, and only committing takes the turn of the writers.
+pub struct Transaction<'fs> {
+    fs: &'fs FileSys,
+    /// Root of the branch when the transaction began, the common ancestor
+    /// of a merge.
+    base: BlockId,
+    /// Root of the tree the transaction builds.
+    root: Cell<BlockId>,
+}
+
+impl FileSys {
+    /// Start a transaction on the checked-out branch.
+    pub fn begin(&self) -> Transaction<'_> {
+        // keep the root from being swapped and released before it is pinned
+        let head = self.root.read().unwrap();
+        // once as the base and once as the tree to build on
+        self.pin(*head);
+        self.pin(*head);
+        Transaction {
+            fs: self,
+            base: *head,
+            root: Cell::new(*head),
+        }
+    }
+}
+
+impl Transaction<'_> {
+    /// Merge the changes of the transaction into the checked-out branch, see
+    /// `FileSys::merge`, and make the result its tree. Fails without any
+    /// change to the branch if the changes overlap with ones committed since
+    /// the transaction began.
+    pub fn commit(self) -> Result<(), Conflict> {
+        let _writer = self.fs.writer.write().unwrap();
+        let root = self.fs.merge(self.base, self.root.get(), self.fs.root())?;
+        self.fs.set_root(root);
+        Ok(())
+    }
+    /// Drop every change the transaction made.
+    pub fn abort(self) {}
+    /// A read-only view of the tree of the transaction, changes included.
+    pub fn view(&self) -> RootView<'_> {
+        self.fs.view(self.root.get())
+    }
+    fn tree(&self) -> Tree<'_> {
+        Tree::Work(&self.root)
+    }
+
+    pub fn create_file(&mut self, path: FPath) -> Result<(), CowFsError> {
+        let _writer = self.fs.writer.read().unwrap();
+        self.fs.create_file_in(self.tree(), path)
+    }
+
+    pub fn write_file(&mut self, path: FPath, data: Data) -> Result<(), CowFsError> {
+        let _writer = self.fs.writer.read().unwrap();
+        self.fs.write_file_in(self.tree(), path, data)
+    }
+
+    pub fn write_at(&mut self, path: FPath, offset: usize, data: Data) -> Result<(), CowFsError> {
+        let _writer = self.fs.writer.read().unwrap();
+        self.fs.write_at_in(self.tree(), path, offset, data)
+    }
+
+    pub fn truncate(&mut self, path: FPath, len: usize) -> Result<(), CowFsError> {
+        let _writer = self.fs.writer.read().unwrap();
+        self.fs.truncate_in(self.tree(), path, len)
+    }
+
+    pub fn append(&mut self, path: FPath, data: Data) -> Result<(), CowFsError> {
+        let _writer = self.fs.writer.read().unwrap();
+        self.fs.append_in(self.tree(), path, data)
+    }
+
+    pub fn create_dir(&mut self, path: FPath) -> Result<(), CowFsError> {
+        let _writer = self.fs.writer.read().unwrap();
+        self.fs.create_dir_in(self.tree(), path)
+    }
+
+    pub fn create_link(&mut self, path: FPath, target: FPath) -> Result<(), CowFsError> {
+        let _writer = self.fs.writer.read().unwrap();
+        self.fs.create_link_in(self.tree(), path, target)
+    }
+
+    pub fn create_symlink(&mut self, path: FPath, target: String) -> Result<(), CowFsError> {
+        let _writer = self.fs.writer.read().unwrap();
+        self.fs.create_symlink_in(self.tree(), path, target)
+    }
+
+    pub fn rename(&mut self, from: FPath, to: FPath) -> Result<(), CowFsError> {
+        let _writer = self.fs.writer.read().unwrap();
+        self.fs.rename_in(self.tree(), from, to)
+    }
+
+    pub fn remove(&mut self, path: FPath) -> Result<(), CowFsError> {
+        let _writer = self.fs.writer.read().unwrap();
+        self.fs.remove_in(self.tree(), path)
+    }
+}
+
+impl Drop for Transaction<'_> {
+    fn drop(&mut self) {
+        // after a commit, the branch holds a reference of its own
+        let _writer = self.fs.writer.write().unwrap();
+        self.fs.unpin(self.base);
+        self.fs.unpin(self.root.get());
+        self.fs.settle();
+    }
+}
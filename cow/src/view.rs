@@ -1,53 +1,119 @@
-pub struct FPath<'a>(pub Vec<&'a str>);
-impl<'a> FPath<'a> {
-    pub fn new(s: &'a str) -> anyhow::Result<Self> {
-        if s.starts_with('/') {
-            let x = s[1..]
-                .split('/')
-                .map(|s| {
-                    if s.is_empty() {
-                        Err(anyhow::anyhow!("Path must not contain empty components"))
-                    } else {
-                        Ok(s)
-                    }
-                })
-                .collect::<Result<_, _>>()?;
-            Ok(FPath(x))
-        } else {
-            Err(anyhow::anyhow!("Path must start with `/`"))
+use interface::{FileSystemError, IPath};
+use std::fmt::Display;
+
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct FPath(pub Vec<String>);
+impl FPath {
+    pub fn new(s: &str) -> Result<Self, FileSystemError<Self>> {
+        let Some(rest) = s.strip_prefix('/') else {
+            Err(FileSystemError::PathStartingWithoutSlash)?
+        };
+        if rest.is_empty() {
+            return Ok(FPath(vec![]));
+        }
+        let x = rest
+            .split('/')
+            .map(|s| {
+                if s.is_empty() {
+                    Err(FileSystemError::EmptySegment)
+                } else {
+                    Ok(s.to_owned())
+                }
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(FPath(x))
+    }
+    pub fn root() -> Self {
+        FPath(vec![])
+    }
+    pub fn is_root(&self) -> bool {
+        self.0.is_empty()
+    }
+    /// The path of the entry `name` inside this directory.
+    pub fn join(&self, name: &str) -> Self {
+        let mut path = self.clone();
+        path.0.push(name.to_owned());
+        path
+    }
+}
+
+impl Display for FPath {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.0.is_empty() {
+            return write!(f, "/");
         }
+        for segment in &self.0 {
+            write!(f, "/{}", segment)?;
+        }
+        Ok(())
+    }
+}
+
+impl TryFrom<&str> for FPath {
+    type Error = FileSystemError<Self>;
+
+    fn try_from(raw: &str) -> Result<Self, Self::Error> {
+        FPath::new(raw)
     }
 }
 
-pub struct IntoIter<'a>(std::vec::IntoIter<&'a str>);
-impl<'a> Iterator for IntoIter<'a> {
-    type Item = &'a str;
+impl TryFrom<String> for FPath {
+    type Error = FileSystemError<Self>;
+
+    fn try_from(raw: String) -> Result<Self, Self::Error> {
+        FPath::new(raw.as_str())
+    }
+}
+
+pub struct IntoIter(std::vec::IntoIter<String>);
+impl Iterator for IntoIter {
+    type Item = String;
 
     fn next(&mut self) -> Option<Self::Item> {
         self.0.next()
     }
 }
-impl<'a> IntoIterator for FPath<'a> {
-    type Item = &'a str;
+impl IntoIterator for FPath {
+    type Item = String;
 
-    type IntoIter = IntoIter<'a>;
+    type IntoIter = IntoIter;
 
     fn into_iter(self) -> Self::IntoIter {
         IntoIter(self.0.into_iter())
     }
 }
 
-pub struct Iter<'a>(std::slice::Iter<'a, &'a str>);
-impl<'a> FPath<'a> {
-    pub fn iter<'b: 'a>(&'b self) -> Iter<'a> {
-        Iter(self.0.iter())
+pub struct Iter<'a>(std::slice::Iter<'a, String>);
+impl<'a> Iterator for Iter<'a> {
+    type Item = &'a String;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next()
     }
 }
 
-impl<'a> Iterator for Iter<'a> {
-    type Item = &'a str;
+impl<'p> IPath<'p> for FPath {
+    type Raw = String;
+    type Segment = String;
+    type Iter = Iter<'p>;
 
-    fn next(&mut self) -> Option<Self::Item> {
-        self.0.next().map(|p| *p)
+    fn append(mut self, raw_segment: &Self::Raw) -> Result<Self, FileSystemError<Self>> {
+        if raw_segment.is_empty() {
+            Err(FileSystemError::EmptySegment)?
+        }
+        if raw_segment.contains('/') {
+            Err(FileSystemError::InvalidSegment(raw_segment.to_owned()))?
+        }
+        self.0.push(raw_segment.to_owned());
+        Ok(self)
+    }
+
+    fn parent(mut self) -> Option<(Self, Self::Segment)> {
+        let last = self.0.pop()?;
+        Some((self, last))
+    }
+
+    fn iter(&'p self) -> Self::Iter {
+        Iter(self.0.iter())
     }
 }
@@ -0,0 +1,65 @@
+mod common;
+
+use common::{data, path, read, Instance};
+use cowffs::{block::DATA_BLOCK_SIZE, FileSys};
+
+#[test]
+fn freed_slots_are_reused() {
+    let mut fs = common::with_files(&[("/a", &[1; DATA_BLOCK_SIZE]), ("/b", &[2; DATA_BLOCK_SIZE])]);
+    let mut lens = vec![];
+    for round in 0..10u8 {
+        fs.write_file(path("/a"), data([round; DATA_BLOCK_SIZE])).unwrap();
+        fs.remove(path("/b")).unwrap();
+        fs.create_file(path("/b")).unwrap();
+        lens.push(fs.blocks.len());
+    }
+    // the first round may need more slots than there were, none after it
+    assert!(lens.iter().all(|&len| len == lens[0]), "{:?}", lens);
+    assert_eq!(read(&fs, "/a"), [9; DATA_BLOCK_SIZE]);
+    common::assert_clean(&mut fs);
+}
+
+#[test]
+fn loaded_images_allocate_from_their_bitmap() {
+    let instance = Instance::new("bitmap");
+    let fs = FileSys::fs_disk_init(instance.path()).unwrap();
+    for file in ["/a", "/b", "/c"] {
+        fs.create_file(path(file)).unwrap();
+        fs.write_file(path(file), data(file)).unwrap();
+    }
+    fs.remove(path("/b")).unwrap();
+    fs.fs_disk_dump().unwrap();
+
+    // new blocks go to the slots the image left free, not over its blocks
+    let mut fs = FileSys::fs_disk_load(instance.path()).unwrap();
+    let len = fs.blocks.len();
+    fs.create_file(path("/d")).unwrap();
+    fs.write_file(path("/d"), data("/d")).unwrap();
+    assert_eq!(fs.blocks.len(), len);
+    fs.fs_disk_dump().unwrap();
+    for file in ["/a", "/c", "/d"] {
+        assert_eq!(read(&fs, file), file.as_bytes());
+    }
+    common::assert_clean(&mut fs);
+}
+
+#[test]
+fn gc_sets_a_wrong_bitmap_right() {
+    let instance = Instance::new("wrong-bitmap");
+    let json = instance.path().with_extension("json");
+    let fs = common::with_files(&[("/a", b"a"), ("/b", b"b")]);
+    fs.fs_json_export(&json).unwrap();
+    let mut image: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&json).unwrap()).unwrap();
+    // every slot down as free, and one past the blocks as used
+    let len = image["alloc"]["len"].as_u64().unwrap();
+    image["alloc"] = serde_json::json!({ "bits": [1u64 << len], "len": len + 1, "used": 1 });
+    std::fs::write(&json, image.to_string()).unwrap();
+
+    let mut fs = FileSys::fs_json_import(instance.path(), &json).unwrap();
+    std::fs::remove_file(&json).unwrap();
+    fs.create_file(path("/c")).unwrap();
+    fs.write_file(path("/c"), data(b"c")).unwrap();
+    assert_eq!(read(&fs, "/a"), b"a");
+    assert_eq!(read(&fs, "/b"), b"b");
+    common::assert_clean(&mut fs);
+}
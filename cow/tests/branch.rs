@@ -0,0 +1,56 @@
+mod common;
+
+use common::{data, path, read};
+use cowffs::{branch::MAIN_BRANCH, IReadFileSystem};
+
+#[test]
+fn branches_change_apart_from_each_other() {
+    let mut fs = common::with_files(&[("/a/f", b"base"), ("/b/g", b"shared")]);
+    let base = fs.snapshot("base").unwrap();
+    assert_eq!(fs.branch("base", "work").unwrap(), base);
+    assert!(fs.branch("base", "work").is_err());
+    assert!(fs.branch("base", MAIN_BRANCH).is_err());
+    assert!(fs.branch("missing", "other").is_err());
+
+    fs.checkout("work").unwrap();
+    assert_eq!(fs.head(), "work");
+    fs.write_file(path("/a/f"), data(b"work")).unwrap();
+    fs.create_file(path("/new")).unwrap();
+    fs.checkout(MAIN_BRANCH).unwrap();
+    assert_eq!(read(&fs, "/a/f"), b"base");
+    assert!(fs.metadata(path("/new")).is_err());
+    fs.write_file(path("/a/f"), data(b"main")).unwrap();
+
+    let work = fs.named_root("work").unwrap();
+    assert_eq!(fs.read_file_at(work, path("/a/f")).unwrap().data, b"work");
+    assert_eq!(fs.read_file_at(base, path("/a/f")).unwrap().data, b"base");
+    // what neither branch touched is the same block on both
+    assert_eq!(
+        fs.traverse(work, path("/b")).unwrap(),
+        fs.traverse(fs.root(), path("/b")).unwrap()
+    );
+    let names = fs.list_branches().into_iter().map(|(name, _)| name).collect::<Vec<_>>();
+    assert_eq!(names, [MAIN_BRANCH, "work"]);
+    common::assert_clean(&mut fs);
+}
+
+#[test]
+fn deleting_a_branch_frees_what_only_it_used() {
+    let mut fs = common::with_files(&[("/f", b"base")]);
+    fs.snapshot("base").unwrap();
+    fs.branch("base", "work").unwrap();
+    fs.delete_snapshot("base").unwrap();
+    let used = common::used(&fs);
+
+    fs.checkout("work").unwrap();
+    fs.write_file(path("/f"), data(b"work")).unwrap();
+    assert!(fs.delete_branch("work").is_err());
+    fs.checkout(MAIN_BRANCH).unwrap();
+    assert!(common::used(&fs) > used);
+    fs.delete_branch("work").unwrap();
+    assert!(fs.delete_branch("work").is_err());
+    assert_eq!(common::used(&fs), used);
+    assert!(fs.named_root("work").is_none());
+    assert_eq!(read(&fs, "/f"), b"base");
+    common::assert_clean(&mut fs);
+}
@@ -0,0 +1,118 @@
+mod common;
+
+use common::Instance;
+use std::{
+    io::Write,
+    path::Path,
+    process::{Command, Output, Stdio},
+};
+
+/// Run `cowffs` on `image` with `args`, feeding it `stdin`.
+fn cowffs(image: &Path, args: &[&str], stdin: &[u8]) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_cowffs"))
+        .arg(image)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(stdin).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+/// Run a command that must succeed and return what it printed.
+fn ok(image: &Path, args: &[&str]) -> String {
+    let output = cowffs(image, args, b"");
+    assert!(
+        output.status.success(),
+        "{:?}: {}",
+        args,
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn commands_work_on_an_image() {
+    let instance = Instance::new("cli");
+    let image = instance.path();
+    ok(&image, &["mkfs"]);
+    ok(&image, &["mkdir", "/d"]);
+    let output = cowffs(&image, &["put", "/d/f"], b"from stdin");
+    assert!(output.status.success());
+    ok(&image, &["ln", "/d/f", "/hard"]);
+    ok(&image, &["ln", "-s", "d/f", "/soft"]);
+
+    assert_eq!(ok(&image, &["cat", "/soft"]), "from stdin");
+    assert_eq!(ok(&image, &["ls"]), "d/\nhard\nsoft -> d/f\n");
+    assert_eq!(
+        ok(&image, &["tree"]),
+        "/\n    d/\n        f\n    hard\n    soft -> d/f\n"
+    );
+    let stat = ok(&image, &["stat", "/hard"]);
+    assert!(stat.contains("type:  file\n") && stat.contains("links: 2\n") && stat.contains("size:  10\n"));
+    ok(&image, &["truncate", "/hard", "4"]);
+    assert_eq!(ok(&image, &["cat", "/d/f"]), "from");
+    ok(&image, &["mv", "/hard", "/d/g"]);
+    ok(&image, &["rm", "/soft"]);
+    assert_eq!(ok(&image, &["ls", "/d"]), "f\ng\n");
+
+    // no temporary file is left next to the image
+    let mut tmp = image.as_os_str().to_owned();
+    tmp.push(".tmp");
+    assert!(!Path::new(&tmp).exists());
+}
+
+#[test]
+fn snapshots_and_diff_survive_the_image() {
+    let instance = Instance::new("cli-snapshot");
+    let image = instance.path();
+    ok(&image, &["mkfs"]);
+    cowffs(&image, &["put", "/f"], b"one");
+    ok(&image, &["snapshot", "old"]);
+    cowffs(&image, &["put", "/f"], b"two");
+    cowffs(&image, &["put", "/g"], b"");
+    ok(&image, &["snapshot", "new"]);
+    let names = ok(&image, &["snapshot"]);
+    let names = names
+        .lines()
+        .map(|line| line.split('\t').next().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(names, ["new", "old"]);
+    assert_eq!(ok(&image, &["diff", "old", "new"]), "M /f\n+ /g\n");
+    assert_eq!(ok(&image, &["diff", "old", "main"]), "M /f\n+ /g\n");
+    assert!(!cowffs(&image, &["diff", "old", "missing"], b"").status.success());
+}
+
+#[test]
+fn failures_leave_the_image_alone() {
+    let instance = Instance::new("cli-fail");
+    let image = instance.path();
+    ok(&image, &["mkfs"]);
+    let before = std::fs::read(&image).unwrap();
+    for args in [&["cat", "/missing"][..], &["rm", "/"], &["mkdir", "/"], &["bogus"]] {
+        let output = cowffs(&image, args, b"");
+        assert!(!output.status.success(), "{:?}", args);
+        assert!(!output.stderr.is_empty());
+    }
+    assert_eq!(std::fs::read(&image).unwrap(), before);
+    assert_eq!(ok(&image, &["scrub"]), "");
+}
+
+#[test]
+fn confirm_asks_before_rewriting() {
+    let instance = Instance::new("cli-confirm");
+    let image = instance.path();
+    ok(&image, &["mkfs"]);
+    let output = Command::new(env!("CARGO_BIN_EXE_cowffs"))
+        .arg("--confirm")
+        .arg(&image)
+        .args(["mkdir", "/d"])
+        .stdin(Stdio::null())
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("will be rewritten"));
+    assert_eq!(ok(&image, &["ls"]), "d/\n");
+}
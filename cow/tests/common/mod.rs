@@ -0,0 +1,98 @@
+#![allow(dead_code)]
+
+use cowffs::{
+    block::{Block, Data},
+    view::FPath,
+    FileSys, IReadFileSystem,
+};
+use std::path::{Path, PathBuf};
+
+pub fn path(s: &str) -> FPath {
+    FPath::new(s).unwrap()
+}
+
+pub fn read(fs: &FileSys, s: &str) -> Vec<u8> {
+    fs.read_file(path(s)).unwrap().data
+}
+
+pub fn data(bytes: impl AsRef<[u8]>) -> Data {
+    Data::new(bytes)
+}
+
+/// A file system in memory holding the files `files`, with their parent
+/// directories.
+pub fn with_files(files: &[(&str, &[u8])]) -> FileSys {
+    let fs = FileSys::fs_disk_init(PathBuf::new()).unwrap();
+    for (file, content) in files {
+        let file = path(file);
+        for depth in 1..file.0.len() {
+            let _ = fs.create_dir(FPath(file.0[..depth].to_vec()));
+        }
+        fs.create_file(file.clone()).unwrap();
+        fs.write_file(file, data(content)).unwrap();
+    }
+    fs
+}
+
+/// Number of slots that hold a block.
+pub fn used(fs: &FileSys) -> usize {
+    fs.blocks
+        .iter()
+        .filter(|block| !matches!(*block.read().unwrap(), Block::Free))
+        .count()
+}
+
+/// Check that the reference counts and the allocator are right and that no
+/// block has been left behind that nothing refers to.
+pub fn assert_clean(fs: &mut FileSys) {
+    assert_eq!(fs.verify_refs(), vec![]);
+    let report = fs.gc();
+    assert_eq!(report.freed, vec![]);
+    assert_eq!(report.mismatches, vec![]);
+    assert_eq!(report.unmarked, vec![]);
+    assert_eq!(report.leaked, vec![]);
+}
+
+/// Damage the image at `instance` where it holds `bytes`, which it must hold
+/// exactly once.
+pub fn damage(instance: &Path, bytes: &[u8]) {
+    let mut image = std::fs::read(instance).unwrap();
+    let found = image
+        .windows(bytes.len())
+        .enumerate()
+        .filter(|(_, window)| *window == bytes)
+        .map(|(offset, _)| offset)
+        .collect::<Vec<_>>();
+    assert_eq!(found.len(), 1, "{:?} found at {:?}", bytes, found);
+    image[found[0]] ^= 0xff;
+    std::fs::write(instance, image).unwrap();
+}
+
+/// An instance file of its own for a test, removed along with what commits
+/// leave next to it once the test is done.
+pub struct Instance(PathBuf);
+
+impl Instance {
+    pub fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("cowffs-{}-{}", std::process::id(), name));
+        let instance = Instance(path);
+        instance.clean();
+        instance
+    }
+    pub fn path(&self) -> PathBuf {
+        self.0.clone()
+    }
+    fn clean(&self) {
+        let mut tmp = self.0.as_os_str().to_owned();
+        tmp.push(".tmp");
+        for file in [self.0.as_path(), Path::new(&tmp)] {
+            let _ = std::fs::remove_file(file);
+        }
+    }
+}
+
+impl Drop for Instance {
+    fn drop(&mut self) {
+        self.clean();
+    }
+}
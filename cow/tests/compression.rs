@@ -0,0 +1,68 @@
+mod common;
+
+use common::{data, path, read, Instance};
+use cowffs::{disk::Compression, FileSys};
+
+/// Bytes that no codec makes smaller.
+fn noise(len: usize) -> Vec<u8> {
+    let mut state = 0x2545f4914f6cdd1du64;
+    (0..len)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state as u8
+        })
+        .collect()
+}
+
+/// Dump a file system holding `files` compressed with `compression` and
+/// return the size of the image.
+fn dump(instance: &Instance, compression: Compression, files: &[(&str, &[u8])]) -> u64 {
+    let mut fs = FileSys::fs_disk_init(instance.path()).unwrap();
+    fs.compression = compression;
+    for (file, content) in files {
+        fs.create_file(path(file)).unwrap();
+        fs.write_file(path(file), data(content)).unwrap();
+    }
+    fs.fs_disk_dump().unwrap();
+    std::fs::metadata(instance.path()).unwrap().len()
+}
+
+#[test]
+fn every_codec_round_trips() {
+    let text = b"the same words over and over again ".repeat(1000);
+    let noise = noise(10_000);
+    let files: &[(&str, &[u8])] = &[("/text", &text), ("/noise", &noise), ("/empty", b"")];
+    let raw = dump(&Instance::new("raw"), Compression::Raw, files);
+    for compression in [Compression::Raw, Compression::Lz4, Compression::Deflate] {
+        let instance = Instance::new(&format!("{:?}", compression));
+        let len = dump(&instance, compression, files);
+        if compression != Compression::Raw {
+            assert!(len < raw, "{:?} image of {} bytes, raw {}", compression, len, raw);
+        }
+        let fs = FileSys::fs_disk_load(instance.path()).unwrap();
+        assert_eq!(fs.compression, compression);
+        for (file, content) in files {
+            assert_eq!(read(&fs, file), *content);
+        }
+    }
+}
+
+#[test]
+fn records_of_different_codecs_mix() {
+    let instance = Instance::new("mixed");
+    let text = b"compressible ".repeat(1000);
+    dump(&instance, Compression::Lz4, &[("/lz4", &text)]);
+    let mut fs = FileSys::fs_disk_load(instance.path()).unwrap();
+    fs.compression = Compression::Deflate;
+    fs.create_file(path("/deflate")).unwrap();
+    fs.write_file(path("/deflate"), data(&text)).unwrap();
+    fs.fs_disk_dump().unwrap();
+
+    let fs = FileSys::fs_disk_load(instance.path()).unwrap();
+    assert_eq!(fs.compression, Compression::Deflate);
+    assert_eq!(read(&fs, "/lz4"), text);
+    assert_eq!(read(&fs, "/deflate"), text);
+    assert!(fs.scrub().unwrap().is_empty());
+}
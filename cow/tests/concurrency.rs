@@ -0,0 +1,112 @@
+mod common;
+
+use common::{data, path};
+use cowffs::{FileSys, IMeta, IReadFileSystem};
+use std::{
+    path::PathBuf,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+#[test]
+fn links_change_the_tree_in_one_step() {
+    let fs = FileSys::fs_disk_init(PathBuf::new()).unwrap();
+    fs.create_dir(path("/d")).unwrap();
+    fs.create_file(path("/f")).unwrap();
+    fs.write_file(path("/f"), data(b"content")).unwrap();
+    let done = AtomicBool::new(false);
+    std::thread::scope(|scope| {
+        scope.spawn(|| {
+            for _ in 0..300 {
+                fs.create_link(path("/d/l"), path("/f")).unwrap();
+                fs.remove(path("/d/l")).unwrap();
+            }
+            done.store(true, Ordering::Release);
+        });
+        for _ in 0..4 {
+            scope.spawn(|| {
+                while !done.load(Ordering::Acquire) {
+                    let reader = fs.reader();
+                    let names = 1 + reader.read_dir(path("/d")).unwrap().len();
+                    assert_eq!(reader.metadata(path("/f")).unwrap().nlink(), names);
+                }
+            });
+        }
+    });
+    assert_eq!(fs.metadata(path("/f")).unwrap().nlink(), 1);
+    assert!(fs.verify_refs().is_empty());
+}
+
+#[test]
+fn concurrent_transactions_commit_or_conflict() {
+    let mut fs = common::with_files(&[("/counter", b"0")]);
+    std::thread::scope(|scope| {
+        for thread in 0..4 {
+            let fs = &fs;
+            scope.spawn(move || {
+                for round in 0..50 {
+                    // a file of its own, which always merges
+                    let mut transaction = fs.begin();
+                    let own = format!("/t{}-{}", thread, round);
+                    transaction.create_file(path(&own)).unwrap();
+                    transaction.commit().unwrap();
+                    // a file all threads change, retried until it commits; the
+                    // thread is written along, as two commits that leave the
+                    // same content merge
+                    loop {
+                        let mut transaction = fs.begin();
+                        let count = transaction.view().read_file(path("/counter")).unwrap().data;
+                        let count = String::from_utf8(count).unwrap();
+                        let count = count.split(' ').next().unwrap().parse::<usize>().unwrap();
+                        let count = data(format!("{} {}", count + 1, thread));
+                        transaction.write_file(path("/counter"), count).unwrap();
+                        if transaction.commit().is_ok() {
+                            break;
+                        }
+                    }
+                }
+            });
+        }
+    });
+    assert_eq!(fs.read_dir(path("/")).unwrap().len(), 1 + 4 * 50);
+    let count = String::from_utf8(common::read(&fs, "/counter")).unwrap();
+    assert!(count.starts_with("200 "), "{}", count);
+    common::assert_clean(&mut fs);
+}
+
+#[test]
+fn threads_share_one_file_system() {
+    let mut fs = common::with_files(&[]);
+    for thread in 0..4 {
+        fs.create_dir(path(&format!("/{}", thread))).unwrap();
+    }
+    std::thread::scope(|scope| {
+        for thread in 0..4 {
+            let fs = &fs;
+            scope.spawn(move || {
+                for idx in 0..50 {
+                    let file = path(&format!("/{}/{}", thread, idx));
+                    fs.create_file(file.clone()).unwrap();
+                    fs.write_file(file.clone(), data(vec![idx as u8; 5000])).unwrap();
+                    if idx % 2 == 1 {
+                        fs.remove(file).unwrap();
+                    }
+                }
+            });
+            scope.spawn(move || {
+                for _ in 0..200 {
+                    for name in fs.read_dir(path(&format!("/{}", thread))).unwrap_or_default() {
+                        // a file may be gone by the time it is read
+                        if let Ok(content) = fs.read_file(path(&format!("/{}/{}", thread, name))) {
+                            assert!(content.data.iter().all(|&byte| byte == content.data[0]));
+                        }
+                    }
+                }
+            });
+        }
+    });
+    for thread in 0..4 {
+        assert_eq!(fs.read_dir(path(&format!("/{}", thread))).unwrap().len(), 25);
+        assert_eq!(common::read(&fs, &format!("/{}/48", thread)), vec![48; 5000]);
+    }
+    common::assert_clean(&mut fs);
+}
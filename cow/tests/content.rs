@@ -0,0 +1,127 @@
+mod common;
+
+use common::{data, path, read, with_files};
+use cowffs::{
+    block::{Block, DATA_BLOCK_SIZE, DIRECT_BLOCKS, INDIRECT_BLOCKS},
+    FileSys, FileSystemError, IReadFileSystem,
+};
+use std::path::PathBuf;
+
+#[test]
+fn write_at_past_the_largest_offset_fails() {
+    let fs = FileSys::fs_disk_init(PathBuf::new()).unwrap();
+    fs.create_file(path("/f")).unwrap();
+    fs.write_file(path("/f"), data(b"abc")).unwrap();
+
+    let err = fs.write_at(path("/f"), usize::MAX, data(b"x")).unwrap_err();
+    assert!(matches!(err, FileSystemError::FileTooLarge(_)));
+    assert_eq!(read(&fs, "/f"), b"abc");
+    // an empty write ends where it starts
+    fs.write_at(path("/f"), usize::MAX, data(b"")).unwrap();
+    assert_eq!(read(&fs, "/f"), b"abc");
+}
+
+#[test]
+fn offset_operations() {
+    let fs = with_files(&[("/f", b"hello")]);
+    assert_eq!(fs.read_at(path("/f"), 1, 3).unwrap().data, b"ell");
+    assert_eq!(fs.read_at(path("/f"), 3, 10).unwrap().data, b"lo");
+    assert_eq!(fs.read_at(path("/f"), 10, 1).unwrap().data, b"");
+
+    fs.write_at(path("/f"), 3, data(b"p!")).unwrap();
+    assert_eq!(read(&fs, "/f"), b"help!");
+    // a gap past the end is filled with zeros
+    fs.write_at(path("/f"), 7, data(b"x")).unwrap();
+    assert_eq!(read(&fs, "/f"), b"help!\0\0x");
+    fs.append(path("/f"), data(b"yz")).unwrap();
+    assert_eq!(read(&fs, "/f"), b"help!\0\0xyz");
+    fs.truncate(path("/f"), 4).unwrap();
+    assert_eq!(read(&fs, "/f"), b"help");
+    fs.truncate(path("/f"), 6).unwrap();
+    assert_eq!(read(&fs, "/f"), b"help\0\0");
+    fs.truncate(path("/f"), 0).unwrap();
+    assert_eq!(read(&fs, "/f"), b"");
+}
+
+#[test]
+fn offset_operations_need_a_file() {
+    let fs = with_files(&[("/d/f", b"")]);
+    assert!(matches!(
+        fs.write_at(path("/d"), 0, data(b"x")),
+        Err(FileSystemError::OperateFileOnDir(_))
+    ));
+    assert!(matches!(
+        fs.append(path("/missing"), data(b"x")),
+        Err(FileSystemError::FileNotInDir(_))
+    ));
+    assert!(fs.read_at(path("/d"), 0, 1).is_err());
+}
+
+/// The children of the `INode` of the file `file`.
+fn children(fs: &FileSys, file: &str) -> Vec<Block> {
+    let (_, inode) = fs.traverse(fs.root(), path(file)).unwrap();
+    let children = fs.inode(inode).children;
+    children.into_iter().map(|id| fs[id].read().unwrap().clone()).collect()
+}
+
+fn live_blocks(fs: &FileSys) -> usize {
+    let blocks = fs.blocks.iter().map(|block| block.read().unwrap().clone());
+    blocks.filter(|block| !matches!(block, Block::Free)).count()
+}
+
+#[test]
+fn files_are_cut_into_chunks() {
+    let content = (0..DIRECT_BLOCKS * DATA_BLOCK_SIZE)
+        .map(|idx| idx as u8)
+        .collect::<Vec<_>>();
+    let fs = with_files(&[("/f", &content)]);
+    let chunks = children(&fs, "/f");
+    assert_eq!(chunks.len(), DIRECT_BLOCKS);
+    assert!(chunks
+        .iter()
+        .all(|chunk| matches!(chunk, Block::Data(data) if data.data.len() == DATA_BLOCK_SIZE)));
+
+    // one chunk more takes a level of `Indirect` blocks
+    fs.append(path("/f"), data(b"!")).unwrap();
+    assert!(matches!(
+        &children(&fs, "/f")[..],
+        [Block::Indirect(ids)] if ids.len() == DIRECT_BLOCKS + 1
+    ));
+    let mut expected = content.clone();
+    expected.push(b'!');
+    assert_eq!(read(&fs, "/f"), expected);
+    let boundary = DIRECT_BLOCKS * DATA_BLOCK_SIZE - 2;
+    assert_eq!(fs.read_at(path("/f"), boundary, 10).unwrap().data, expected[boundary..]);
+
+    // and a cut back drops it again
+    fs.truncate(path("/f"), DATA_BLOCK_SIZE + 1).unwrap();
+    assert!(matches!(&children(&fs, "/f")[..], [Block::Data(_), Block::Data(data)] if data.data.len() == 1));
+    assert_eq!(read(&fs, "/f"), content[..DATA_BLOCK_SIZE + 1]);
+}
+
+#[test]
+fn writes_to_large_files_share_untouched_chunks() {
+    let mut fs = with_files(&[("/f", b"")]);
+    // past what two levels of `Indirect` blocks hold
+    let end = DIRECT_BLOCKS * INDIRECT_BLOCKS * DATA_BLOCK_SIZE + 5;
+    fs.write_at(path("/f"), end, data(b"end")).unwrap();
+    assert_eq!(fs.read_at(path("/f"), end - 2, 10).unwrap().data, b"\0\0end");
+    assert_eq!(fs.read_at(path("/f"), 12345, 3).unwrap().data, b"\0\0\0");
+
+    let before = live_blocks(&fs);
+    fs.write_at(path("/f"), 5 * DATA_BLOCK_SIZE, data(b"middle")).unwrap();
+    // a chunk, the `Indirect` blocks above it and the path up to the root
+    assert!(
+        live_blocks(&fs) - before < 8,
+        "{} new blocks",
+        live_blocks(&fs) - before
+    );
+    assert_eq!(
+        fs.read_at(path("/f"), 5 * DATA_BLOCK_SIZE - 1, 8).unwrap().data,
+        b"\0middle\0"
+    );
+
+    fs.truncate(path("/f"), 3).unwrap();
+    assert!(matches!(&children(&fs, "/f")[..], [Block::Data(data)] if data.data == b"\0\0\0"));
+    common::assert_clean(&mut fs);
+}
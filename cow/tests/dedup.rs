@@ -0,0 +1,42 @@
+mod common;
+
+use common::{assert_clean, data, path, read, Instance};
+use cowffs::{block::DATA_BLOCK_SIZE, FileSys};
+
+#[test]
+fn identical_chunks_share_blocks() {
+    let instance = Instance::new("dedup");
+    let mut fs = FileSys::fs_disk_init(instance.path()).unwrap();
+    fs.set_dedup(true);
+    let content = (0..3 * DATA_BLOCK_SIZE)
+        .map(|idx| (idx / DATA_BLOCK_SIZE) as u8)
+        .collect::<Vec<_>>();
+    for file in ["/a", "/b"] {
+        fs.create_file(path(file)).unwrap();
+        fs.write_file(path(file), data(&content)).unwrap();
+    }
+    let report = fs.space_report();
+    assert_eq!(report.data_blocks, 3);
+    assert_eq!(report.saved(), content.len() as u64);
+
+    // a change to one file leaves the other alone
+    fs.write_at(path("/b"), 0, data(b"x")).unwrap();
+    assert_eq!(read(&fs, "/a"), content);
+    assert_eq!(read(&fs, "/b")[..2], [b'x', 0]);
+    assert_eq!(fs.space_report().data_blocks, 4);
+    assert_clean(&mut fs);
+
+    fs.fs_disk_dump().unwrap();
+    let fs = FileSys::fs_disk_load(instance.path()).unwrap();
+    assert!(fs.dedup_enabled());
+    assert_eq!(read(&fs, "/a"), content);
+    assert_eq!(fs.space_report().data_blocks, 4);
+}
+
+#[test]
+fn without_dedup_every_chunk_has_a_block() {
+    let fs = common::with_files(&[("/a", b"same"), ("/b", b"same")]);
+    assert!(!fs.dedup_enabled());
+    assert_eq!(fs.space_report().data_blocks, 2);
+    assert_eq!(fs.space_report().saved(), 0);
+}
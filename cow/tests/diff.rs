@@ -0,0 +1,58 @@
+mod common;
+
+use common::{data, path};
+use cowffs::{branch::MAIN_BRANCH, diff::Change};
+
+#[test]
+fn diff_lists_every_changed_entry_in_path_order() {
+    let mut fs = common::with_files(&[("/a/f", b"one"), ("/a/g", b"same"), ("/b/h", b""), ("/t", b"file")]);
+    let old = fs.snapshot("old").unwrap();
+    fs.write_file(path("/a/f"), data(b"two")).unwrap();
+    fs.remove(path("/b/h")).unwrap();
+    fs.remove(path("/b")).unwrap();
+    fs.remove(path("/t")).unwrap();
+    fs.create_dir(path("/t")).unwrap();
+    fs.create_file(path("/t/new")).unwrap();
+    fs.create_dir(path("/c")).unwrap();
+
+    assert_eq!(
+        fs.diff(old, fs.root()),
+        [
+            Change::Modified(path("/a/f")),
+            Change::Removed(path("/b")),
+            Change::Removed(path("/b/h")),
+            Change::Added(path("/c")),
+            Change::TypeChanged(path("/t")),
+        ]
+    );
+    assert!(fs.diff(old, old).is_empty());
+}
+
+#[test]
+fn diff_compares_branches() {
+    let mut fs = common::with_files(&[("/shared/f", b"base"), ("/f", b"base")]);
+    fs.snapshot("base").unwrap();
+    fs.branch("base", "work").unwrap();
+    fs.checkout("work").unwrap();
+    fs.write_file(path("/f"), data(b"work")).unwrap();
+    fs.create_file(path("/only-work")).unwrap();
+    fs.checkout(MAIN_BRANCH).unwrap();
+    fs.create_file(path("/only-main")).unwrap();
+
+    let main = fs.named_root(MAIN_BRANCH).unwrap();
+    let work = fs.named_root("work").unwrap();
+    assert_eq!(
+        fs.diff(main, work),
+        [
+            Change::Modified(path("/f")),
+            Change::Removed(path("/only-main")),
+            Change::Added(path("/only-work")),
+        ]
+    );
+    let base = fs.named_root("base").unwrap();
+    assert_eq!(
+        fs.diff(base, work),
+        [Change::Modified(path("/f")), Change::Added(path("/only-work"))]
+    );
+    common::assert_clean(&mut fs);
+}
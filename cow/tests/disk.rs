@@ -0,0 +1,159 @@
+mod common;
+
+use common::{path, read, Instance};
+use cowffs::{
+    block::DATA_BLOCK_SIZE,
+    disk::{is_image, BLOCK_SIZE},
+    FileSys, IReadFileSystem,
+};
+
+#[test]
+fn legacy_json_image_loads_and_is_rewritten() {
+    let instance = Instance::new("legacy");
+    let content = (0..3 * DATA_BLOCK_SIZE + 7).map(|idx| idx as u8).collect::<Vec<_>>();
+    // the format of earlier releases, with a file kept whole in one block
+    let legacy = format!(
+        r#"[{{"INode":{{"ref_cnt":1,"children":[1]}}}},
+            {{"DirEntries":[{{"name":"d","btype":"Dir","inode":2}},null,{{"name":"f","btype":"File","inode":4}}]}},
+            {{"INode":{{"ref_cnt":1,"children":[3]}}}},
+            {{"DirEntries":[]}},
+            {{"INode":{{"ref_cnt":1,"children":[5]}}}},
+            {{"Data":{{"data":{:?}}}}}]"#,
+        content
+    );
+    std::fs::write(instance.path(), legacy).unwrap();
+
+    let fs = FileSys::fs_disk_load(instance.path()).unwrap();
+    assert_eq!(read(&fs, "/f"), content);
+    assert!(fs.read_dir(path("/d")).unwrap().is_empty());
+    assert!(fs.verify_refs().is_empty());
+
+    fs.fs_disk_dump().unwrap();
+    assert!(is_image(&instance.path()).unwrap());
+    let fs = FileSys::fs_disk_load(instance.path()).unwrap();
+    assert_eq!(read(&fs, "/f"), content);
+}
+
+#[test]
+fn image_round_trips() {
+    let instance = Instance::new("round-trip");
+    let fs = FileSys::fs_disk_init(instance.path()).unwrap();
+    fs.create_dir(path("/d")).unwrap();
+    fs.create_file(path("/d/f")).unwrap();
+    fs.write_file(path("/d/f"), common::data(b"hello")).unwrap();
+    fs.fs_disk_dump().unwrap();
+
+    let fs = FileSys::fs_disk_load(instance.path()).unwrap();
+    assert_eq!(read(&fs, "/d/f"), b"hello");
+}
+
+#[test]
+fn torn_superblock_falls_back_to_the_previous_commit() {
+    let instance = Instance::new("torn");
+    let fs = FileSys::fs_disk_init(instance.path()).unwrap();
+    fs.create_file(path("/f")).unwrap();
+    fs.write_file(path("/f"), common::data(b"old")).unwrap();
+    fs.fs_disk_dump().unwrap();
+    let before = std::fs::read(instance.path()).unwrap();
+    fs.write_file(path("/f"), common::data(b"new")).unwrap();
+    fs.fs_disk_dump().unwrap();
+    let after = std::fs::read(instance.path()).unwrap();
+
+    // the commit wrote a single superblock, leaving the previous one alone
+    let slots = (0..2)
+        .filter(|slot| {
+            let range = slot * BLOCK_SIZE..(slot + 1) * BLOCK_SIZE;
+            before[range.clone()] != after[range]
+        })
+        .collect::<Vec<_>>();
+    let [slot] = slots[..] else {
+        panic!("commit changed superblock slots {:?}", slots)
+    };
+    // a crash that only got part of the new superblock to disk
+    let mut torn = after.clone();
+    let offset = slot * BLOCK_SIZE;
+    torn[offset + 64..offset + BLOCK_SIZE].copy_from_slice(&before[offset + 64..offset + BLOCK_SIZE]);
+    std::fs::write(instance.path(), &torn).unwrap();
+
+    let fs = FileSys::fs_disk_load(instance.path()).unwrap();
+    assert_eq!(read(&fs, "/f"), b"old");
+    // the next commit goes to the torn slot again, and loads
+    fs.write_file(path("/f"), common::data(b"newer")).unwrap();
+    fs.fs_disk_dump().unwrap();
+    let fs = FileSys::fs_disk_load(instance.path()).unwrap();
+    assert_eq!(read(&fs, "/f"), b"newer");
+}
+
+#[test]
+fn dump_leaves_pinned_trees_out_of_the_image() {
+    let instance = Instance::new("pinned");
+    let fs = FileSys::fs_disk_init(instance.path()).unwrap();
+    fs.create_file(path("/f")).unwrap();
+    fs.write_file(path("/f"), common::data(b"old")).unwrap();
+    fs.fs_disk_dump().unwrap();
+
+    let reader = fs.reader();
+    fs.write_file(path("/f"), common::data(b"new")).unwrap();
+    let mut transaction = fs.begin();
+    transaction.create_file(path("/g")).unwrap();
+    transaction.write_file(path("/g"), common::data(b"pending")).unwrap();
+    fs.fs_disk_dump().unwrap();
+    let fsck = FileSys::fsck(instance.path()).unwrap();
+    assert!(fsck.problems.is_empty(), "{:?}", fsck.problems);
+    assert_eq!(reader.read_file(path("/f")).unwrap().data, b"old");
+    drop(reader);
+
+    // blocks of the transaction that a commit links in get written after all
+    transaction.commit().unwrap();
+    fs.fs_disk_dump().unwrap();
+    let fsck = FileSys::fsck(instance.path()).unwrap();
+    assert!(fsck.problems.is_empty(), "{:?}", fsck.problems);
+    let fs = FileSys::fs_disk_load(instance.path()).unwrap();
+    assert_eq!(read(&fs, "/f"), b"new");
+    assert_eq!(read(&fs, "/g"), b"pending");
+}
+
+#[test]
+fn blocks_not_read_in_survive_a_rewrite() {
+    let instance = Instance::new("unread");
+    let content = (0..3 * DATA_BLOCK_SIZE).map(|idx| idx as u8).collect::<Vec<_>>();
+    let fs = FileSys::fs_disk_init(instance.path()).unwrap();
+    for file in ["/keep", "/churn"] {
+        fs.create_file(path(file)).unwrap();
+        fs.write_file(path(file), common::data(&content)).unwrap();
+    }
+    fs.fs_disk_dump().unwrap();
+
+    // enough commits for the garbage to get the image rewritten, with
+    // `/keep` never read in
+    let mut fs = FileSys::fs_disk_load(instance.path()).unwrap();
+    let len = std::fs::metadata(instance.path()).unwrap().len();
+    let mut shrank = false;
+    for round in 0..20u8 {
+        fs.write_file(path("/churn"), common::data(vec![round; 2 * DATA_BLOCK_SIZE]))
+            .unwrap();
+        fs.fs_disk_dump().unwrap();
+        shrank |= std::fs::metadata(instance.path()).unwrap().len() <= len;
+    }
+    assert!(shrank);
+    assert_eq!(read(&fs, "/keep"), content);
+    common::assert_clean(&mut fs);
+
+    // the stored reference counts are those of the image
+    let mut fs = FileSys::fs_disk_load(instance.path()).unwrap();
+    assert_eq!(read(&fs, "/keep"), content);
+    assert_eq!(read(&fs, "/churn"), vec![19; 2 * DATA_BLOCK_SIZE]);
+    common::assert_clean(&mut fs);
+}
+
+#[test]
+fn long_names_round_trip() {
+    let instance = Instance::new("names");
+    let name = format!("/{}", "n".repeat(u16::MAX as usize + 10));
+    let fs = FileSys::fs_disk_init(instance.path()).unwrap();
+    fs.create_file(path(&name)).unwrap();
+    fs.fs_disk_dump().unwrap();
+
+    let fs = FileSys::fs_disk_load(instance.path()).unwrap();
+    assert_eq!(fs.read_dir(path("/")).unwrap(), vec![name[1..].to_owned()]);
+}
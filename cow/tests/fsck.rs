@@ -0,0 +1,87 @@
+mod common;
+
+use common::{damage, data, path, read, Instance};
+use cowffs::{
+    block::{DATA_BLOCK_SIZE, INDIRECT_BLOCKS},
+    fsck::Problem,
+    FileSys, IReadFileSystem,
+};
+
+#[test]
+fn fsck_finds_nothing_wrong_with_a_good_image() {
+    let instance = Instance::new("fsck-good");
+    let fs = FileSys::fs_disk_init(instance.path()).unwrap();
+    fs.create_dir(path("/d")).unwrap();
+    fs.create_file(path("/d/f")).unwrap();
+    fs.create_link(path("/l"), path("/d/f")).unwrap();
+    fs.create_symlink(path("/s"), "d/f".to_owned()).unwrap();
+    fs.fs_disk_dump().unwrap();
+
+    let fsck = FileSys::fsck(instance.path()).unwrap();
+    assert!(fsck.problems.is_empty(), "{:?}", fsck.problems);
+}
+
+#[test]
+fn fsck_repairs_damaged_files() {
+    let instance = Instance::new("fsck-repair");
+    let fs = FileSys::fs_disk_init(instance.path()).unwrap();
+    fs.create_dir(path("/d")).unwrap();
+    fs.create_file(path("/d/f")).unwrap();
+    // three chunks, the second of which gets damaged
+    let mut content = vec![b'a'; 4096];
+    content.extend(b"b".repeat(4096));
+    content.extend(b"c".repeat(100));
+    fs.write_file(path("/d/f"), data(&content)).unwrap();
+    fs.create_file(path("/d/g")).unwrap();
+    fs.write_file(path("/d/g"), data(b"untouched")).unwrap();
+    fs.fs_disk_dump().unwrap();
+    damage(&instance.path(), &b"b".repeat(4096));
+
+    let fsck = FileSys::fsck(instance.path()).unwrap();
+    let [Problem::Corrupt { tree, path: at, .. }] = &fsck.problems[..] else {
+        panic!("{:?}", fsck.problems)
+    };
+    assert_eq!((tree.as_str(), at), ("branch main", &path("/d/f")));
+
+    // the file is cut short before its first bad block
+    let repaired = fsck.repaired;
+    assert_eq!(read(&repaired, "/d/f"), vec![b'a'; 4096]);
+    assert_eq!(read(&repaired, "/d/g"), b"untouched");
+    repaired.fs_disk_dump().unwrap();
+    let fsck = FileSys::fsck(instance.path()).unwrap();
+    assert!(fsck.problems.is_empty(), "{:?}", fsck.problems);
+    assert_eq!(fsck.repaired.read_dir(path("/d")).unwrap().len(), 2);
+}
+
+#[test]
+fn fsck_cuts_files_at_their_first_bad_chunk() {
+    let instance = Instance::new("fsck-cut");
+    let fs = FileSys::fs_disk_init(instance.path()).unwrap();
+    // the first `Indirect` block holds chunks 0 to 511, the second chunk 512
+    let chunks = INDIRECT_BLOCKS + 1;
+    let chunk = |idx: usize| {
+        let mut chunk = format!("<chunk {:06}>", idx).into_bytes();
+        chunk.resize(DATA_BLOCK_SIZE, 0);
+        chunk
+    };
+    let content = (0..chunks).flat_map(chunk).collect::<Vec<_>>();
+    fs.create_file(path("/f")).unwrap();
+    fs.write_file(path("/f"), data(&content)).unwrap();
+    fs.fs_disk_dump().unwrap();
+    damage(&instance.path(), b"<chunk 000100>");
+
+    let fsck = FileSys::fsck(instance.path()).unwrap();
+    let [Problem::Corrupt { path: at, .. }] = &fsck.problems[..] else {
+        panic!("{:?}", fsck.problems)
+    };
+    assert_eq!(at, &path("/f"));
+    assert_eq!(read(&fsck.repaired, "/f"), content[..100 * DATA_BLOCK_SIZE]);
+    fsck.repaired.fs_disk_dump().unwrap();
+
+    let mut fs = FileSys::fs_disk_load(instance.path()).unwrap();
+    assert_eq!(read(&fs, "/f"), content[..100 * DATA_BLOCK_SIZE]);
+    fs.append(path("/f"), data(chunk(100))).unwrap();
+    assert_eq!(read(&fs, "/f"), content[..101 * DATA_BLOCK_SIZE]);
+    common::assert_clean(&mut fs);
+    assert!(FileSys::fsck(instance.path()).unwrap().problems.is_empty());
+}
@@ -0,0 +1,82 @@
+mod common;
+
+use common::{data, path};
+use cowffs::{FileSys, FileSystemError, IFileSystem, IMeta, IReadFileSystem};
+
+/// Build a small tree through `IFileSystem` alone, as tools that switch
+/// between backends do.
+fn tree() -> FileSys {
+    let mut fs = <FileSys as IFileSystem>::init();
+    IFileSystem::create_dir(&mut fs, path("/d")).unwrap();
+    IFileSystem::create_file(&mut fs, path("/d/f")).unwrap();
+    IFileSystem::write_file(&mut fs, path("/d/f"), data(b"content")).unwrap();
+    IFileSystem::create_link(&mut fs, path("/l"), path("/d/f")).unwrap();
+    fs
+}
+
+#[test]
+fn operations_work_through_the_interface() {
+    let mut fs = tree();
+    let mut names = fs.read_dir(path("/")).unwrap();
+    names.sort();
+    assert_eq!(names, ["d", "l"]);
+    assert_eq!(fs.read_dir(path("/d")).unwrap(), ["f"]);
+    assert_eq!(fs.read_file(path("/l")).unwrap().data, b"content");
+
+    let meta = fs.metadata(path("/d")).unwrap();
+    assert!(meta.is_dir() && !meta.is_file() && !meta.is_symlink());
+    let meta = fs.metadata(path("/d/f")).unwrap();
+    assert!(meta.is_file() && meta.nlink() == 2);
+
+    IFileSystem::write_file(&mut fs, path("/l"), data(b"through the link")).unwrap();
+    assert_eq!(fs.read_file(path("/d/f")).unwrap().data, b"through the link");
+    IFileSystem::remove(&mut fs, path("/d/f")).unwrap();
+    IFileSystem::remove(&mut fs, path("/d")).unwrap();
+    assert_eq!(fs.read_dir(path("/")).unwrap(), ["l"]);
+    assert_eq!(fs.metadata(path("/l")).unwrap().nlink(), 1);
+    common::assert_clean(&mut fs);
+}
+
+#[test]
+fn operations_fail_like_the_reference() {
+    let mut fs = tree();
+    assert!(matches!(
+        IFileSystem::create_file(&mut fs, path("/d/f")),
+        Err(FileSystemError::AlreadyExists(_))
+    ));
+    assert!(matches!(
+        IFileSystem::create_dir(&mut fs, path("/l")),
+        Err(FileSystemError::AlreadyExists(_))
+    ));
+    assert!(matches!(
+        fs.read_file(path("/missing")),
+        Err(FileSystemError::FileNotInDir(_))
+    ));
+    assert!(matches!(
+        fs.read_dir(path("/d/f")),
+        Err(FileSystemError::IndexOnFile(_))
+    ));
+    assert!(matches!(
+        fs.read_file(path("/d/f/g")),
+        Err(FileSystemError::IndexOnFile(_))
+    ));
+    assert!(matches!(
+        fs.read_file(path("/d")),
+        Err(FileSystemError::OperateFileOnDir(_))
+    ));
+    assert!(matches!(
+        IFileSystem::create_link(&mut fs, path("/m"), path("/d")),
+        Err(FileSystemError::OperateFileOnDir(_))
+    ));
+    assert!(matches!(
+        IFileSystem::remove(&mut fs, path("/d")),
+        Err(FileSystemError::RemoveNonEmptyDir(_))
+    ));
+    assert!(matches!(
+        IFileSystem::remove(&mut fs, path("/")),
+        Err(FileSystemError::OperateOnRoot)
+    ));
+    // failed operations change nothing
+    assert_eq!(fs.read_file(path("/d/f")).unwrap().data, b"content");
+    common::assert_clean(&mut fs);
+}
@@ -0,0 +1,60 @@
+mod common;
+
+use common::{data, path, read};
+use cowffs::{FileSystemError, IMeta, IReadFileSystem};
+
+fn nlink(fs: &cowffs::FileSys, s: &str) -> usize {
+    fs.metadata(path(s)).unwrap().nlink()
+}
+
+#[test]
+fn link_counts_follow_links_and_removes() {
+    let mut fs = common::with_files(&[("/a", b"shared")]);
+    fs.create_dir(path("/d")).unwrap();
+    fs.create_link(path("/b"), path("/a")).unwrap();
+    fs.create_link(path("/d/c"), path("/b")).unwrap();
+    assert_eq!(nlink(&fs, "/a"), 3);
+    assert_eq!(read(&fs, "/d/c"), b"shared");
+    // every name sees a write to any of them
+    fs.write_file(path("/d/c"), data(b"changed")).unwrap();
+    assert_eq!(read(&fs, "/a"), b"changed");
+
+    fs.remove(path("/a")).unwrap();
+    assert_eq!(nlink(&fs, "/b"), 2);
+    fs.remove(path("/d/c")).unwrap();
+    assert_eq!(nlink(&fs, "/b"), 1);
+    assert_eq!(read(&fs, "/b"), b"changed");
+    common::assert_clean(&mut fs);
+}
+
+#[test]
+fn snapshots_keep_their_own_link_counts() {
+    let mut fs = common::with_files(&[("/a", b"shared")]);
+    fs.create_link(path("/b"), path("/a")).unwrap();
+    fs.snapshot("s").unwrap();
+    fs.remove(path("/b")).unwrap();
+    assert_eq!(nlink(&fs, "/a"), 1);
+    let view = fs.open_snapshot("s").unwrap();
+    assert_eq!(view.metadata(path("/a")).unwrap().nlink(), 2);
+    assert_eq!(view.read_file(path("/b")).unwrap().data, b"shared");
+    common::assert_clean(&mut fs);
+}
+
+#[test]
+fn creating_over_a_taken_name_fails() {
+    let mut fs = common::with_files(&[("/a", b""), ("/d/f", b"")]);
+    fs.create_link(path("/b"), path("/a")).unwrap();
+    let taken = [
+        fs.create_file(path("/b")),
+        fs.create_dir(path("/d")),
+        fs.create_link(path("/a"), path("/a")),
+        fs.create_link(path("/d"), path("/a")),
+        fs.create_symlink(path("/b"), "a".to_owned()),
+    ];
+    for result in taken {
+        assert!(matches!(result, Err(FileSystemError::AlreadyExists(_))), "{:?}", result);
+    }
+    assert_eq!(nlink(&fs, "/a"), 2);
+    assert_eq!(fs.read_dir(path("/d")).unwrap(), ["f"]);
+    common::assert_clean(&mut fs);
+}
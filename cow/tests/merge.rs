@@ -0,0 +1,100 @@
+mod common;
+
+use common::{assert_clean, data, path, read, with_files};
+use cowffs::{merge::Conflict, FileSystemError, IReadFileSystem};
+
+fn conflicts(result: Result<(), Conflict>) -> Vec<String> {
+    result.unwrap_err().paths.iter().map(ToString::to_string).collect()
+}
+
+#[test]
+fn disjoint_changes_merge() {
+    let mut fs = with_files(&[("/a/x", b"x"), ("/b/y", b"y")]);
+    let mut ours = fs.begin();
+    let mut theirs = fs.begin();
+    ours.write_file(path("/a/x"), data(b"ours")).unwrap();
+    ours.create_file(path("/a/new")).unwrap();
+    theirs.write_file(path("/b/y"), data(b"theirs")).unwrap();
+    theirs.remove(path("/b/y")).unwrap();
+    theirs.create_dir(path("/c")).unwrap();
+    theirs.commit().unwrap();
+    ours.commit().unwrap();
+
+    assert_eq!(read(&fs, "/a/x"), b"ours");
+    assert_eq!(read(&fs, "/a/new"), b"");
+    assert!(fs.read_dir(path("/b")).unwrap().is_empty());
+    assert!(fs.read_dir(path("/c")).unwrap().is_empty());
+    assert_clean(&mut fs);
+}
+
+#[test]
+fn changes_to_the_same_file_conflict() {
+    let mut fs = with_files(&[("/a/x", b"x"), ("/b", b"b")]);
+    let mut ours = fs.begin();
+    let mut theirs = fs.begin();
+    ours.write_file(path("/a/x"), data(b"ours")).unwrap();
+    ours.write_file(path("/b"), data(b"ours")).unwrap();
+    theirs.append(path("/a/x"), data(b"theirs")).unwrap();
+    theirs.commit().unwrap();
+
+    let result = ours.commit();
+    assert_eq!(conflicts(result), ["/a/x"]);
+    // the branch keeps the first commit, and nothing of the failed one
+    assert_eq!(read(&fs, "/a/x"), b"xtheirs");
+    assert_eq!(read(&fs, "/b"), b"b");
+    assert_clean(&mut fs);
+}
+
+#[test]
+fn removing_what_the_other_side_changed_conflicts() {
+    let mut fs = with_files(&[("/f", b"f"), ("/d/x", b"x"), ("/e/y", b"y")]);
+    let mut ours = fs.begin();
+    let mut theirs = fs.begin();
+    ours.remove(path("/f")).unwrap();
+    ours.remove(path("/d/x")).unwrap();
+    ours.remove(path("/d")).unwrap();
+    ours.write_file(path("/e/y"), data(b"ours")).unwrap();
+    theirs.write_file(path("/f"), data(b"theirs")).unwrap();
+    theirs.create_file(path("/d/z")).unwrap();
+    theirs.remove(path("/e/y")).unwrap();
+    theirs.commit().unwrap();
+
+    let result = ours.commit();
+    assert_eq!(conflicts(result), ["/d", "/e/y", "/f"]);
+    assert_eq!(read(&fs, "/f"), b"theirs");
+    assert!(matches!(
+        fs.read_file(path("/e/y")),
+        Err(FileSystemError::FileNotInDir(_))
+    ));
+    assert_clean(&mut fs);
+}
+
+#[test]
+fn the_same_change_on_both_sides_merges() {
+    let mut fs = with_files(&[("/f", b"f")]);
+    let mut ours = fs.begin();
+    let mut theirs = fs.begin();
+    for transaction in [&mut ours, &mut theirs] {
+        transaction.create_dir(path("/d")).unwrap();
+        transaction.create_file(path("/d/x")).unwrap();
+        transaction.write_file(path("/f"), data(b"same")).unwrap();
+    }
+    ours.create_file(path("/d/ours")).unwrap();
+    theirs.create_file(path("/d/theirs")).unwrap();
+    theirs.commit().unwrap();
+    ours.commit().unwrap();
+
+    assert_eq!(read(&fs, "/f"), b"same");
+    let mut names = fs.read_dir(path("/d")).unwrap();
+    names.sort();
+    assert_eq!(names, ["ours", "theirs", "x"]);
+    assert_clean(&mut fs);
+}
+
+#[test]
+fn conflicts_name_every_path() {
+    let conflict = Conflict {
+        paths: vec![path("/a"), path("/b/c")],
+    };
+    assert_eq!(conflict.to_string(), "conflicting changes to `/a`, `/b/c`");
+}
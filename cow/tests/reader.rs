@@ -0,0 +1,54 @@
+mod common;
+
+use common::{assert_clean, data, path, read, with_files};
+use cowffs::{IMeta, IReadFileSystem};
+
+#[test]
+fn reader_keeps_its_tree_while_writers_move_on() {
+    let mut fs = with_files(&[("/d/a", b"a"), ("/b", b"b")]);
+    let reader = fs.reader();
+    fs.write_file(path("/d/a"), data(b"changed")).unwrap();
+    fs.remove(path("/b")).unwrap();
+    fs.create_link(path("/d/l"), path("/d/a")).unwrap();
+
+    assert_ne!(reader.root(), fs.root());
+    assert_eq!(reader.read_file(path("/d/a")).unwrap().data, b"a");
+    assert_eq!(reader.read_file(path("/b")).unwrap().data, b"b");
+    assert_eq!(reader.metadata(path("/d/a")).unwrap().nlink(), 1);
+    assert_eq!(reader.read_dir(path("/d")).unwrap(), ["a"]);
+    assert_eq!(read(&fs, "/d/a"), b"changed");
+
+    // the old tree goes once nothing pins it anymore
+    drop(reader);
+    assert_clean(&mut fs);
+}
+
+#[test]
+fn readers_dropped_during_writes_release_their_trees() {
+    let mut fs = with_files(&[("/f", b"0")]);
+    std::thread::scope(|scope| {
+        let fs = &fs;
+        scope.spawn(move || {
+            for round in 1..=200 {
+                fs.write_file(path("/f"), data(round.to_string())).unwrap();
+            }
+        });
+        for _ in 0..4 {
+            scope.spawn(move || {
+                let mut last = 0;
+                while last < 200 {
+                    let reader = fs.reader();
+                    let content = reader.read_file(path("/f")).unwrap().data;
+                    let round = String::from_utf8(content).unwrap().parse::<usize>().unwrap();
+                    // the same reader reads the same content, however late
+                    assert_eq!(reader.read_file(path("/f")).unwrap().data, round.to_string().as_bytes());
+                    assert!(round >= last);
+                    last = round;
+                }
+            });
+        }
+    });
+    // pins let go of while a writer was busy are dropped by the next one
+    fs.write_file(path("/f"), data(b"done")).unwrap();
+    assert_clean(&mut fs);
+}
@@ -0,0 +1,45 @@
+mod common;
+
+use common::{data, path};
+use cowffs::{block::DATA_BLOCK_SIZE, IReadFileSystem};
+
+#[test]
+fn removing_a_file_frees_its_blocks() {
+    let mut fs = common::with_files(&[("/keep", b"")]);
+    let used = common::used(&fs);
+    fs.create_dir(path("/d")).unwrap();
+    fs.create_file(path("/d/f")).unwrap();
+    fs.write_file(path("/d/f"), data(vec![1; 5 * DATA_BLOCK_SIZE])).unwrap();
+    assert!(common::used(&fs) > used + 5);
+    fs.remove(path("/d/f")).unwrap();
+    fs.remove(path("/d")).unwrap();
+    assert_eq!(common::used(&fs), used);
+    common::assert_clean(&mut fs);
+}
+
+#[test]
+fn overwritten_versions_are_freed() {
+    let mut fs = common::with_files(&[("/f", &[0; DATA_BLOCK_SIZE])]);
+    let used = common::used(&fs);
+    for round in 1..10u8 {
+        fs.write_file(path("/f"), data([round; DATA_BLOCK_SIZE])).unwrap();
+        assert_eq!(common::used(&fs), used);
+    }
+    common::assert_clean(&mut fs);
+}
+
+#[test]
+fn holders_keep_blocks_alive() {
+    let mut fs = common::with_files(&[("/f", b"old")]);
+    let used = common::used(&fs);
+    fs.snapshot("s").unwrap();
+    let reader = fs.reader();
+    fs.remove(path("/f")).unwrap();
+    assert_eq!(reader.read_file(path("/f")).unwrap().data, b"old");
+    assert_eq!(fs.root_holders().len(), 3);
+    drop(reader);
+    fs.delete_snapshot("s").unwrap();
+    assert!(common::used(&fs) < used);
+    assert_eq!(fs.root_holders(), [fs.root()]);
+    common::assert_clean(&mut fs);
+}
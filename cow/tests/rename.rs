@@ -0,0 +1,72 @@
+mod common;
+
+use common::{assert_clean, path, read, with_files};
+use cowffs::{FileSystemError, IMeta, IReadFileSystem};
+
+#[test]
+fn rename_moves_and_replaces() {
+    let mut fs = with_files(&[("/a", b"a"), ("/b", b"b"), ("/d/x", b"x")]);
+    fs.rename(path("/a"), path("/d/a")).unwrap();
+    assert_eq!(read(&fs, "/d/a"), b"a");
+    assert!(fs.read_file(path("/a")).is_err());
+
+    // an existing file is replaced
+    fs.rename(path("/b"), path("/d/x")).unwrap();
+    assert_eq!(read(&fs, "/d/x"), b"b");
+    let mut names = fs.read_dir(path("/d")).unwrap();
+    names.sort();
+    assert_eq!(names, ["a", "x"]);
+
+    // and so is an empty directory by a directory
+    fs.create_dir(path("/e")).unwrap();
+    fs.rename(path("/d"), path("/e")).unwrap();
+    assert_eq!(read(&fs, "/e/a"), b"a");
+    assert_clean(&mut fs);
+}
+
+#[test]
+fn rename_keeps_link_counts() {
+    let mut fs = with_files(&[("/a", b"a"), ("/b", b"b")]);
+    fs.create_link(path("/l"), path("/b")).unwrap();
+    // both names of the same node: nothing happens
+    fs.rename(path("/l"), path("/b")).unwrap();
+    assert_eq!(fs.metadata(path("/b")).unwrap().nlink(), 2);
+
+    // replacing one name of a linked file leaves the other
+    fs.rename(path("/a"), path("/l")).unwrap();
+    assert_eq!(read(&fs, "/l"), b"a");
+    assert_eq!(fs.metadata(path("/l")).unwrap().nlink(), 1);
+    assert_eq!(fs.metadata(path("/b")).unwrap().nlink(), 1);
+    assert_clean(&mut fs);
+}
+
+#[test]
+fn rename_refuses_what_posix_refuses() {
+    let fs = with_files(&[("/f", b"f"), ("/d/x", b"x"), ("/e/y", b"y")]);
+    fs.create_dir(path("/d/sub")).unwrap();
+    assert!(matches!(
+        fs.rename(path("/d"), path("/d/sub/d")),
+        Err(FileSystemError::MoveIntoSubtree(_))
+    ));
+    assert!(matches!(
+        fs.rename(path("/d"), path("/e")),
+        Err(FileSystemError::RemoveNonEmptyDir(_))
+    ));
+    assert!(matches!(
+        fs.rename(path("/f"), path("/d")),
+        Err(FileSystemError::OperateFileOnDir(_))
+    ));
+    assert!(matches!(
+        fs.rename(path("/d"), path("/f")),
+        Err(FileSystemError::OperateDirOnFile(_))
+    ));
+    assert!(matches!(
+        fs.rename(path("/missing"), path("/g")),
+        Err(FileSystemError::FileNotInDir(_))
+    ));
+    assert!(matches!(
+        fs.rename(path("/"), path("/g")),
+        Err(FileSystemError::OperateOnRoot)
+    ));
+    assert_eq!(read(&fs, "/f"), b"f");
+}
@@ -0,0 +1,79 @@
+mod common;
+
+use common::{damage, data, path, read, Instance};
+use cowffs::{FileSys, FileSystemError, IReadFileSystem};
+
+fn image(instance: &Instance) -> FileSys {
+    let fs = FileSys::fs_disk_init(instance.path()).unwrap();
+    fs.create_dir(path("/d")).unwrap();
+    for (file, content) in [
+        ("/d/bad", "content that gets damaged"),
+        ("/d/good", "content that stays"),
+    ] {
+        fs.create_file(path(file)).unwrap();
+        fs.write_file(path(file), data(content)).unwrap();
+    }
+    fs.fs_disk_dump().unwrap();
+    fs
+}
+
+#[test]
+fn scrub_finds_damaged_blocks() {
+    let instance = Instance::new("scrub");
+    let fs = image(&instance);
+    assert!(fs.scrub().unwrap().is_empty());
+
+    damage(&instance.path(), b"content that gets damaged");
+    let bad = fs.scrub().unwrap();
+    assert_eq!(bad.len(), 1);
+    assert_eq!(bad[0].tree, "branch main");
+    assert_eq!(bad[0].path, path("/d/bad"));
+}
+
+#[test]
+fn damaged_blocks_fail_reads_until_removed() {
+    let instance = Instance::new("scrub-load");
+    image(&instance);
+    damage(&instance.path(), b"content that gets damaged");
+
+    // blocks are only read, and found bad, once used
+    let fs = FileSys::fs_disk_load(instance.path()).unwrap();
+    assert!(fs.corrupt_blocks().is_empty());
+    let err = fs.read_file(path("/d/bad")).unwrap_err();
+    assert!(matches!(err, FileSystemError::CorruptBlock(..)), "{:?}", err);
+    assert_eq!(fs.corrupt_blocks().len(), 1);
+    assert_eq!(read(&fs, "/d/good"), b"content that stays");
+    // a commit keeps the block bad
+    fs.write_file(path("/d/good"), data(b"changed")).unwrap();
+    fs.fs_disk_dump().unwrap();
+    assert_eq!(fs.scrub().unwrap().len(), 1);
+    let fs = FileSys::fs_disk_load(instance.path()).unwrap();
+    assert!(fs.read_file(path("/d/bad")).is_err());
+
+    fs.remove(path("/d/bad")).unwrap();
+    assert!(fs.corrupt_blocks().is_empty());
+    fs.fs_disk_dump().unwrap();
+    assert!(fs.scrub().unwrap().is_empty());
+    assert_eq!(read(&fs, "/d/good"), b"changed");
+}
+
+#[test]
+fn damaged_blocks_are_reported_where_they_are() {
+    let instance = Instance::new("scrub-path");
+    let fs = FileSys::fs_disk_init(instance.path()).unwrap();
+    fs.create_dir(path("/d")).unwrap();
+    fs.create_dir(path("/d/subdirectory")).unwrap();
+    fs.create_file(path("/d/subdirectory/f")).unwrap();
+    fs.create_symlink(path("/l"), "/d".to_owned()).unwrap();
+    fs.fs_disk_dump().unwrap();
+    // the entries of `/d`
+    damage(&instance.path(), b"subdirectory");
+
+    let fs = FileSys::fs_disk_load(instance.path()).unwrap();
+    for requested in ["/d/subdirectory/f", "/l/subdirectory/f"] {
+        match fs.read_file(path(requested)) {
+            Err(FileSystemError::CorruptBlock(at, _)) => assert_eq!(at, path("/d"), "{}", requested),
+            other => panic!("{}: {:?}", requested, other),
+        }
+    }
+}
@@ -0,0 +1,62 @@
+mod common;
+
+use common::{data, path, read};
+use cowffs::IReadFileSystem;
+
+#[test]
+fn snapshots_share_every_block() {
+    let mut fs = common::with_files(&[("/a/f", b"one"), ("/g", b"two")]);
+    let len = fs.blocks.len();
+    let used = common::used(&fs);
+    let root = fs.snapshot("s").unwrap();
+    assert_eq!(root, fs.root());
+    assert_eq!((fs.blocks.len(), common::used(&fs)), (len, used));
+    assert_eq!(fs.list_snapshots(), [("s".to_owned(), root)]);
+    common::assert_clean(&mut fs);
+}
+
+#[test]
+fn snapshots_stay_as_they_were_taken() {
+    let mut fs = common::with_files(&[("/a/f", b"one"), ("/g", b"two")]);
+    fs.snapshot("s").unwrap();
+    fs.write_file(path("/a/f"), data(b"changed")).unwrap();
+    fs.remove(path("/g")).unwrap();
+    fs.create_dir(path("/h")).unwrap();
+
+    let view = fs.open_snapshot("s").unwrap();
+    assert_eq!(view.read_file(path("/a/f")).unwrap().data, b"one");
+    assert_eq!(view.read_file(path("/g")).unwrap().data, b"two");
+    assert!(view.metadata(path("/h")).is_err());
+    assert_eq!(read(&fs, "/a/f"), b"changed");
+    assert!(fs.metadata(path("/g")).is_err());
+    common::assert_clean(&mut fs);
+}
+
+#[test]
+fn snapshot_names_are_unique() {
+    let mut fs = common::with_files(&[("/f", b"one")]);
+    fs.snapshot("s").unwrap();
+    fs.write_file(path("/f"), data(b"two")).unwrap();
+    assert!(fs.snapshot("s").is_err());
+    let view = fs.open_snapshot("s").unwrap();
+    assert_eq!(view.read_file(path("/f")).unwrap().data, b"one");
+    assert!(fs.open_snapshot("t").is_err());
+    assert!(fs.delete_snapshot("t").is_err());
+}
+
+#[test]
+fn deleting_a_snapshot_frees_what_only_it_used() {
+    let mut fs = common::with_files(&[("/f", b"one")]);
+    let used = common::used(&fs);
+    fs.snapshot("s").unwrap();
+    fs.remove(path("/f")).unwrap();
+    fs.create_file(path("/f")).unwrap();
+    fs.write_file(path("/f"), data(b"two")).unwrap();
+    assert!(common::used(&fs) > used);
+
+    fs.delete_snapshot("s").unwrap();
+    assert_eq!(common::used(&fs), used);
+    assert!(fs.list_snapshots().is_empty());
+    assert_eq!(read(&fs, "/f"), b"two");
+    common::assert_clean(&mut fs);
+}
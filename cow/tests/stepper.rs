@@ -0,0 +1,65 @@
+mod common;
+
+use common::{data, path, read};
+use cowffs::{
+    stepper::{Root, Stepper},
+    FileSystemError, IReadFileSystem,
+};
+
+#[test]
+fn writes_shadow_the_path_up_to_a_new_root() {
+    let mut fs = common::with_files(&[("/a/b/f", b"old"), ("/a/c/g", b"untouched")]);
+    let reader = fs.reader();
+    let old = reader.root();
+    let before = fs
+        .blocks
+        .iter()
+        .map(|block| format!("{:?}", block.read().unwrap()))
+        .collect::<Vec<_>>();
+    fs.write_file(path("/a/b/f"), data(b"new")).unwrap();
+
+    // every node on the path got a new `INode`, everything else is shared
+    let new = fs.root();
+    assert_ne!(old, new);
+    for changed in ["/a", "/a/b", "/a/b/f"] {
+        assert_ne!(
+            fs.traverse(old, path(changed)).unwrap(),
+            fs.traverse(new, path(changed)).unwrap()
+        );
+    }
+    for shared in ["/a/c", "/a/c/g"] {
+        assert_eq!(
+            fs.traverse(old, path(shared)).unwrap(),
+            fs.traverse(new, path(shared)).unwrap()
+        );
+    }
+    // and no block of the old tree was modified in place; free slots may
+    // have been taken
+    let after = fs.blocks.iter().map(|block| format!("{:?}", block.read().unwrap()));
+    for (before, after) in before.iter().zip(after).filter(|(before, _)| *before != "Free") {
+        assert_eq!(*before, after);
+    }
+    assert_eq!(reader.read_file(path("/a/b/f")).unwrap().data, b"old");
+    assert_eq!(read(&fs, "/a/b/f"), b"new");
+    drop(reader);
+    common::assert_clean(&mut fs);
+}
+
+#[test]
+fn the_trail_records_where_the_stepper_physically_stands() {
+    let fs = common::with_files(&[("/a/b/f", b"")]);
+    fs.create_symlink(path("/l"), "a/b".into()).unwrap();
+    let stepper = Stepper::walk(&fs, Root::Head, path("/l/../b/./f"), true).unwrap();
+    assert_eq!(stepper.physical(&fs), path("/a/b/f"));
+    assert_eq!(stepper.hops, 1);
+    assert_eq!(stepper.current, fs.traverse(fs.root(), path("/a/b/f")).unwrap().1);
+}
+
+#[test]
+fn a_walk_stops_at_the_first_missing_segment() {
+    let fs = common::with_files(&[("/a/f", b"")]);
+    let mut stepper = Stepper::new(&fs, Root::Head, path("/a/x/y"), true);
+    assert!(stepper.step(&fs).unwrap());
+    assert_eq!(stepper.physical(&fs), path("/a"));
+    assert!(matches!(stepper.step(&fs), Err(FileSystemError::FileNotInDir(_))));
+}
@@ -0,0 +1,87 @@
+mod common;
+
+use common::{data, path, read};
+use cowffs::{FileSystemError, IMeta, IReadFileSystem, SYMLINK_HOPS_LIMIT};
+
+#[test]
+fn symlinks_resolve_relative_and_absolute_targets() {
+    let fs = common::with_files(&[("/a/b/f", b"content")]);
+    fs.create_symlink(path("/abs"), "/a/b".into()).unwrap();
+    fs.create_symlink(path("/a/rel"), "b/f".into()).unwrap();
+    fs.create_symlink(path("/a/b/up"), "../rel".into()).unwrap();
+    assert_eq!(read(&fs, "/abs/f"), b"content");
+    assert_eq!(read(&fs, "/a/rel"), b"content");
+    assert_eq!(read(&fs, "/abs/up"), b"content");
+    assert_eq!(fs.read_dir(path("/abs")).unwrap().len(), 2);
+
+    // writes go through the link to its target
+    fs.write_file(path("/a/rel"), data(b"new")).unwrap();
+    assert_eq!(read(&fs, "/a/b/f"), b"new");
+    assert_eq!(fs.read_link(path("/abs")).unwrap(), "/a/b");
+    assert_eq!(fs.read_link(path("/abs/up")).unwrap(), "../rel");
+}
+
+#[test]
+fn the_last_link_is_not_followed_by_metadata_and_remove() {
+    let mut fs = common::with_files(&[("/f", b"content")]);
+    fs.create_symlink(path("/l"), "f".into()).unwrap();
+    assert!(fs.metadata(path("/l")).unwrap().is_symlink());
+    assert!(fs.metadata(path("/f")).unwrap().is_file());
+    fs.remove(path("/l")).unwrap();
+    assert_eq!(read(&fs, "/f"), b"content");
+    common::assert_clean(&mut fs);
+}
+
+#[test]
+fn read_link_needs_a_symlink() {
+    let fs = common::with_files(&[("/d/f", b"")]);
+    for target in ["/d/f", "/d"] {
+        let result = fs.read_link(path(target));
+        assert!(matches!(result, Err(FileSystemError::NotSymlink(_))), "{}", target);
+    }
+    assert!(matches!(
+        fs.read_link(path("/missing")),
+        Err(FileSystemError::FileNotInDir(_))
+    ));
+}
+
+#[test]
+fn dangling_links_fail_when_followed() {
+    let fs = common::with_files(&[]);
+    fs.create_symlink(path("/l"), "missing".into()).unwrap();
+    assert_eq!(fs.read_link(path("/l")).unwrap(), "missing");
+    assert!(matches!(
+        fs.read_file(path("/l")),
+        Err(FileSystemError::FileNotInDir(_))
+    ));
+}
+
+#[test]
+fn symlink_loops_are_reported() {
+    let fs = common::with_files(&[]);
+    fs.create_symlink(path("/a"), "b".into()).unwrap();
+    fs.create_symlink(path("/b"), "a".into()).unwrap();
+    fs.create_symlink(path("/self"), "/self/x".into()).unwrap();
+    for looping in ["/a", "/b/f", "/self"] {
+        let result = fs.read_file(path(looping));
+        assert!(matches!(result, Err(FileSystemError::SymlinkLoop(_))), "{}", looping);
+    }
+    // the links themselves can still be looked at
+    assert!(fs.metadata(path("/a")).unwrap().is_symlink());
+}
+
+#[test]
+fn chains_up_to_the_hop_limit_resolve() {
+    let fs = common::with_files(&[("/f", b"end")]);
+    fs.create_symlink(path("/l0"), "f".into()).unwrap();
+    for hop in 1..=SYMLINK_HOPS_LIMIT {
+        fs.create_symlink(path(&format!("/l{}", hop)), format!("l{}", hop - 1))
+            .unwrap();
+    }
+    // `/l{n}` takes n + 1 hops to reach `/f`
+    assert_eq!(read(&fs, &format!("/l{}", SYMLINK_HOPS_LIMIT - 1)), b"end");
+    assert!(matches!(
+        fs.read_file(path(&format!("/l{}", SYMLINK_HOPS_LIMIT))),
+        Err(FileSystemError::SymlinkLoop(_))
+    ));
+}
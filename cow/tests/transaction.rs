@@ -0,0 +1,54 @@
+mod common;
+
+use common::{assert_clean, data, path, read, with_files};
+use cowffs::{FileSystemError, IReadFileSystem};
+
+#[test]
+fn commit_publishes_every_change_at_once() {
+    let mut fs = with_files(&[("/a", b"a")]);
+    let mut transaction = fs.begin();
+    transaction.write_file(path("/a"), data(b"changed")).unwrap();
+    transaction.create_dir(path("/d")).unwrap();
+    transaction.rename(path("/a"), path("/d/a")).unwrap();
+    // the transaction sees its own changes, the branch none of them
+    assert_eq!(transaction.view().read_file(path("/d/a")).unwrap().data, b"changed");
+    assert_eq!(read(&fs, "/a"), b"a");
+    assert!(fs.read_dir(path("/d")).is_err());
+
+    transaction.commit().unwrap();
+    assert_eq!(read(&fs, "/d/a"), b"changed");
+    assert!(matches!(
+        fs.read_file(path("/a")),
+        Err(FileSystemError::FileNotInDir(_))
+    ));
+    assert_clean(&mut fs);
+}
+
+#[test]
+fn abort_and_drop_leave_nothing_behind() {
+    let mut fs = with_files(&[("/a", b"a")]);
+    let root = fs.root();
+    let mut transaction = fs.begin();
+    transaction.write_file(path("/a"), data(vec![1; 10_000])).unwrap();
+    transaction.create_file(path("/b")).unwrap();
+    transaction.abort();
+    let mut transaction = fs.begin();
+    transaction.remove(path("/a")).unwrap();
+    drop(transaction);
+
+    assert_eq!(fs.root(), root);
+    assert_eq!(read(&fs, "/a"), b"a");
+    assert_clean(&mut fs);
+}
+
+#[test]
+fn failed_operations_leave_the_transaction_as_it_was() {
+    let mut fs = with_files(&[("/a", b"a")]);
+    let mut transaction = fs.begin();
+    transaction.create_file(path("/b")).unwrap();
+    assert!(transaction.create_file(path("/b")).is_err());
+    assert!(transaction.remove(path("/missing")).is_err());
+    transaction.commit().unwrap();
+    assert_eq!(read(&fs, "/b"), b"");
+    assert_clean(&mut fs);
+}
@@ -21,8 +21,23 @@ pub enum FileSystemError<P: Display> {
     OperateDirOnFile(P),
     #[error("cannot remove non-empty directory: `{0}`")]
     RemoveNonEmptyDir(P),
+    #[error("file `{0}` already exists")]
+    AlreadyExists(P),
+    #[error("not a symbolic link: `{0}`")]
+    NotSymlink(P),
+    #[error("too many levels of symbolic links: `{0}`")]
+    SymlinkLoop(P),
+    #[error("cannot move a directory into its own subtree: `{0}`")]
+    MoveIntoSubtree(P),
+    #[error("file would end past the largest possible offset: `{0}`")]
+    FileTooLarge(P),
+    #[error("corrupt block {1} under `{0}`")]
+    CorruptBlock(P, String),
 }
 
+/// How many symbolic links a single path resolution may follow.
+pub const SYMLINK_HOPS_LIMIT: usize = 40;
+
 /* -------------------------------- interface ------------------------------- */
 
 pub trait IPath<'p>:
@@ -44,33 +59,70 @@ pub trait IMeta {
     // any node should fall into one of the following categories
     fn is_file(&self) -> bool;
     fn is_dir(&self) -> bool;
+    fn is_symlink(&self) -> bool;
+    /// number of directory entries naming the node
+    fn nlink(&self) -> usize;
 }
 
-pub trait IFileSystem<'fs> {
+pub trait IReadFileSystem<'fs> {
     type Path<'p>: IPath<'p>;
     type Meta: IMeta;
     type Data;
 
-    fn init() -> Self;
-
     /* --------------------------- metadata operations -------------------------- */
+    /// does not follow a symbolic link in the last segment of `path`
     fn metadata(&self, path: Self::Path<'fs>) -> Result<Self::Meta, FileSystemError<Self::Path<'fs>>>;
 
     /* ----------------------------- file operations ---------------------------- */
-    fn create_file(&mut self, path: Self::Path<'fs>) -> Result<(), FileSystemError<Self::Path<'fs>>>;
     fn read_file(&self, path: Self::Path<'fs>) -> Result<Self::Data, FileSystemError<Self::Path<'fs>>>;
-    fn write_file(&mut self, path: Self::Path<'fs>, data: Self::Data) -> Result<(), FileSystemError<Self::Path<'fs>>>;
+    /// at most `len` bytes from `offset` on; fewer when the file ends before
+    fn read_at(
+        &self, path: Self::Path<'fs>, offset: usize, len: usize,
+    ) -> Result<Self::Data, FileSystemError<Self::Path<'fs>>>;
 
     /* -------------------------- directory operations -------------------------- */
-    fn create_dir(&mut self, path: Self::Path<'fs>) -> Result<(), FileSystemError<Self::Path<'fs>>>;
     fn read_dir(
         &self, path: Self::Path<'fs>,
     ) -> Result<Vec<<Self::Path<'fs> as IPath<'fs>>::Segment>, FileSystemError<Self::Path<'fs>>>;
 
+    /* ----------------------------- link operations ---------------------------- */
+    fn read_link(
+        &self, path: Self::Path<'fs>,
+    ) -> Result<<Self::Path<'fs> as IPath<'fs>>::Raw, FileSystemError<Self::Path<'fs>>>;
+}
+
+pub trait IFileSystem<'fs>: IReadFileSystem<'fs> {
+    fn init() -> Self;
+
+    /* ----------------------------- file operations ---------------------------- */
+    fn create_file(&mut self, path: Self::Path<'fs>) -> Result<(), FileSystemError<Self::Path<'fs>>>;
+    fn write_file(&mut self, path: Self::Path<'fs>, data: Self::Data) -> Result<(), FileSystemError<Self::Path<'fs>>>;
+    /// overwrite the bytes from `offset` on; a gap between the end of the file
+    /// and `offset` is filled with zeros
+    fn write_at(
+        &mut self, path: Self::Path<'fs>, offset: usize, data: Self::Data,
+    ) -> Result<(), FileSystemError<Self::Path<'fs>>>;
+    /// cut the file down, or extend it with zeros, to `len` bytes
+    fn truncate(&mut self, path: Self::Path<'fs>, len: usize) -> Result<(), FileSystemError<Self::Path<'fs>>>;
+    fn append(&mut self, path: Self::Path<'fs>, data: Self::Data) -> Result<(), FileSystemError<Self::Path<'fs>>>;
+
+    /* -------------------------- directory operations -------------------------- */
+    fn create_dir(&mut self, path: Self::Path<'fs>) -> Result<(), FileSystemError<Self::Path<'fs>>>;
+
     /* ----------------------------- link operations ---------------------------- */
     fn create_link(
         &mut self, path: Self::Path<'fs>, target: Self::Path<'fs>,
     ) -> Result<(), FileSystemError<Self::Path<'fs>>>;
+    /// `target` is stored as given; a relative target is resolved against the
+    /// directory holding the link when the link is followed
+    fn create_symlink(
+        &mut self, path: Self::Path<'fs>, target: <Self::Path<'fs> as IPath<'fs>>::Raw,
+    ) -> Result<(), FileSystemError<Self::Path<'fs>>>;
+
+    /* ------------------------------ move operations --------------------------- */
+    /// move the entry `from` to `to`, replacing a file or an empty directory
+    /// already at `to`; neither path follows a symbolic link in its last segment
+    fn rename(&mut self, from: Self::Path<'fs>, to: Self::Path<'fs>) -> Result<(), FileSystemError<Self::Path<'fs>>>;
 
     /* ----------------------------- remove operations --------------------------- */
     fn remove(&mut self, path: Self::Path<'fs>) -> Result<(), FileSystemError<Self::Path<'fs>>>;
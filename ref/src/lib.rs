@@ -1,5 +1,8 @@
 pub use interface::*;
-use std::{collections::HashMap, fmt::Display};
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt::Display,
+};
 
 /* ----------------------------- implementation ----------------------------- */
 
@@ -116,49 +119,61 @@ impl Data {
     }
 }
 
+impl From<Vec<u8>> for Data {
+    fn from(raw: Vec<u8>) -> Self {
+        Self(raw)
+    }
+}
+
+impl AsRef<[u8]> for Data {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
 #[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
 pub struct NodeId(usize);
 
 #[derive(Clone, Debug)]
 pub struct Node {
-    // cnt: usize,
+    /// number of directory entries naming this node
+    cnt: usize,
     inner: NodeInner,
 }
 #[derive(Clone, Debug)]
 pub enum NodeInner {
     File(Data),
     Dir(HashMap<String, NodeId>),
+    /// the target path, as given
+    Symlink(String),
 }
 
 impl Node {
     pub fn with_inner(inner: NodeInner) -> Self {
-        Self {
-            // cnt: 1,
-            inner,
-        }
+        Self { cnt: 1, inner }
     }
     pub fn dir(&self, path: FsPath) -> Result<&HashMap<String, NodeId>, ReffFsError> {
         match &self.inner {
-            NodeInner::File(_) => Err(FileSystemError::IndexOnFile(path)),
             NodeInner::Dir(children) => Ok(children),
+            _ => Err(FileSystemError::IndexOnFile(path)),
         }
     }
     pub fn dir_mut(&mut self, path: FsPath) -> Result<&mut HashMap<String, NodeId>, ReffFsError> {
         match &mut self.inner {
-            NodeInner::File(_) => Err(FileSystemError::IndexOnFile(path)),
             NodeInner::Dir(children) => Ok(children),
+            _ => Err(FileSystemError::IndexOnFile(path)),
         }
     }
     pub fn file(&self, path: FsPath) -> Result<&Data, ReffFsError> {
         match &self.inner {
             NodeInner::File(data) => Ok(data),
-            NodeInner::Dir(_) => Err(FileSystemError::OperateDirOnFile(path)),
+            _ => Err(FileSystemError::OperateDirOnFile(path)),
         }
     }
     pub fn file_mut(&mut self, path: FsPath) -> Result<&mut Data, ReffFsError> {
         match &mut self.inner {
             NodeInner::File(data) => Ok(data),
-            NodeInner::Dir(_) => Err(FileSystemError::OperateDirOnFile(path)),
+            _ => Err(FileSystemError::OperateDirOnFile(path)),
         }
     }
 }
@@ -171,11 +186,21 @@ impl IMeta for Node {
     fn is_dir(&self) -> bool {
         matches!(self.inner, NodeInner::Dir(_))
     }
+
+    fn is_symlink(&self) -> bool {
+        matches!(self.inner, NodeInner::Symlink(_))
+    }
+
+    fn nlink(&self) -> usize {
+        self.cnt
+    }
 }
 
 pub struct ReffFs {
     pub nodes: Vec<Node>,
     pub root: NodeId,
+    /// nodes whose last link is gone, reused by `fresh`
+    pub free: Vec<NodeId>,
 }
 
 impl std::ops::Index<NodeId> for ReffFs {
@@ -202,83 +227,241 @@ impl ReffFs {
         Ok(self[dir].dir_mut(path.clone())?)
     }
     pub fn traverse_id(&self, path: FsPath) -> Result<NodeId, ReffFsError> {
-        let mut current = self.root;
-        for segment in path.clone() {
-            let next = *self[current]
-                .dir(path.clone())?
-                .get(&segment.to_string())
-                .ok_or(FileSystemError::FileNotInDir(path.clone()))?;
-            current = next;
+        Ok(*self.resolve(path, true)?.last().expect("the root is never popped"))
+    }
+    /// Like `traverse_id`, but a symbolic link in the last segment is not followed.
+    pub fn traverse_id_nofollow(&self, path: FsPath) -> Result<NodeId, ReffFsError> {
+        Ok(*self.resolve(path, false)?.last().expect("the root is never popped"))
+    }
+    /// Resolve `path` to the nodes from the root down to the one it names.
+    fn resolve(&self, path: FsPath, follow_last: bool) -> Result<Vec<NodeId>, ReffFsError> {
+        // the directories from the root down to the current node
+        let mut stack = vec![self.root];
+        let mut pending = path.clone().into_iter().map(|s| s.to_string()).collect::<VecDeque<_>>();
+        let mut hops = 0;
+        loop {
+            let current = *stack.last().expect("the root is never popped");
+            if let NodeInner::Symlink(target) = &self[current].inner {
+                if follow_last || !pending.is_empty() {
+                    hops += 1;
+                    if hops > SYMLINK_HOPS_LIMIT {
+                        Err(FileSystemError::SymlinkLoop(path.clone()))?
+                    }
+                    stack.pop();
+                    if target.starts_with('/') {
+                        stack.truncate(1);
+                    }
+                    for segment in target.split('/').rev() {
+                        pending.push_front(segment.to_owned());
+                    }
+                    continue;
+                }
+            }
+            let Some(segment) = pending.pop_front() else {
+                return Ok(stack);
+            };
+            let children = self[current].dir(path.clone())?;
+            match segment.as_str() {
+                "" | "." => {}
+                ".." => {
+                    if stack.len() > 1 {
+                        stack.pop();
+                    }
+                }
+                _ => {
+                    let next = *children
+                        .get(&segment)
+                        .ok_or(FileSystemError::FileNotInDir(path.clone()))?;
+                    stack.push(next);
+                }
+            }
+        }
+    }
+    /// Drop one of the links to `node`, recycling it when it was the last.
+    fn unlink(&mut self, node: NodeId) {
+        self[node].cnt -= 1;
+        if self[node].cnt == 0 {
+            // last link gone, drop the contents and recycle the node
+            self[node].inner = NodeInner::File(Data(vec![]));
+            self.free.push(node);
         }
-        Ok(current)
     }
     pub fn fresh(&mut self, node: Node) -> NodeId {
+        if let Some(id) = self.free.pop() {
+            self[id] = node;
+            return id;
+        }
         let id = NodeId(self.nodes.len());
         self.nodes.push(node);
         id
     }
 }
 
-impl<'fs> IFileSystem<'fs> for ReffFs {
+impl<'fs> IReadFileSystem<'fs> for ReffFs {
     type Path<'p> = FsPath;
 
     type Meta = Node;
 
     type Data = Data;
 
+    fn metadata(&self, path: Self::Path<'fs>) -> Result<Self::Meta, ReffFsError> {
+        let node = self.traverse_id_nofollow(path)?;
+        Ok(self[node].clone())
+    }
+
+    fn read_file(&self, path: Self::Path<'fs>) -> Result<Self::Data, ReffFsError> {
+        let node = self.traverse(path.clone())?;
+        node.file(path.clone()).cloned()
+    }
+
+    fn read_at(&self, path: Self::Path<'fs>, offset: usize, len: usize) -> Result<Self::Data, ReffFsError> {
+        let data = self.traverse(path.clone())?.file(path)?;
+        let start = offset.min(data.0.len());
+        let end = offset.saturating_add(len).min(data.0.len());
+        Ok(Data::new(&data.0[start..end]))
+    }
+
+    fn read_dir(&self, path: Self::Path<'fs>) -> Result<Vec<FileName>, ReffFsError> {
+        let node = self.traverse(path.clone())?;
+        let children = node.dir(path.clone())?;
+        children.into_iter().map(|(name, _)| FileName::new(name)).collect()
+    }
+
+    fn read_link(&self, path: Self::Path<'fs>) -> Result<String, ReffFsError> {
+        let node = self.traverse_id_nofollow(path.clone())?;
+        match &self[node].inner {
+            NodeInner::Symlink(target) => Ok(target.clone()),
+            _ => Err(FileSystemError::NotSymlink(path)),
+        }
+    }
+}
+
+impl<'fs> IFileSystem<'fs> for ReffFs {
     fn init() -> Self {
         let nodes = vec![Node::with_inner(NodeInner::Dir(HashMap::new()))];
         let root = NodeId(0);
-        Self { nodes, root }
-    }
-
-    fn metadata(&self, path: Self::Path<'fs>) -> Result<Self::Meta, ReffFsError> {
-        self.traverse(path).cloned()
+        let free = vec![];
+        Self { nodes, root, free }
     }
 
     fn create_file(&mut self, path: Self::Path<'fs>) -> Result<(), ReffFsError> {
-        let (parent, name) = path.parent().ok_or(FileSystemError::OperateOnRoot)?;
+        let (parent, name) = path.clone().parent().ok_or(FileSystemError::OperateOnRoot)?;
+        if self.traverse_dir_mut(parent.clone())?.contains_key(&name.to_string()) {
+            Err(FileSystemError::AlreadyExists(path))?
+        }
         let new_file = self.fresh(Node::with_inner(NodeInner::File(Data(vec![]))));
         let dir = self.traverse_dir_mut(parent)?;
         dir.insert(name.to_string(), new_file);
         Ok(())
     }
 
-    fn read_file(&self, path: Self::Path<'fs>) -> Result<Self::Data, ReffFsError> {
-        let node = self.traverse(path.clone())?;
-        node.file(path.clone()).cloned()
-    }
-
     fn write_file(&mut self, path: Self::Path<'fs>, data: Self::Data) -> Result<(), ReffFsError> {
-        let (parent, name) = path.clone().parent().ok_or(FileSystemError::OperateOnRoot)?;
-        let dir = self.traverse_dir_mut(parent)?;
-        let node = *dir
-            .get_mut(&name.to_string())
-            .ok_or(FileSystemError::FileNotInDir(path.clone()))?;
+        if path.0.is_empty() {
+            Err(FileSystemError::OperateOnRoot)?
+        }
+        let node = self.traverse_id(path.clone())?;
         let fdata = self[node].file_mut(path.clone())?;
         *fdata = data.clone();
         Ok(())
     }
 
+    fn write_at(&mut self, path: Self::Path<'fs>, offset: usize, data: Self::Data) -> Result<(), ReffFsError> {
+        let node = self.traverse_id(path.clone())?;
+        let end = offset
+            .checked_add(data.0.len())
+            .ok_or(FileSystemError::FileTooLarge(path.clone()))?;
+        let fdata = self[node].file_mut(path)?;
+        if data.0.is_empty() {
+            return Ok(());
+        }
+        if fdata.0.len() < end {
+            fdata.0.resize(end, 0);
+        }
+        fdata.0[offset..end].copy_from_slice(&data.0);
+        Ok(())
+    }
+
+    fn truncate(&mut self, path: Self::Path<'fs>, len: usize) -> Result<(), ReffFsError> {
+        let node = self.traverse_id(path.clone())?;
+        self[node].file_mut(path)?.0.resize(len, 0);
+        Ok(())
+    }
+
+    fn append(&mut self, path: Self::Path<'fs>, data: Self::Data) -> Result<(), ReffFsError> {
+        let node = self.traverse_id(path.clone())?;
+        self[node].file_mut(path)?.0.extend(data.0);
+        Ok(())
+    }
+
     fn create_dir(&mut self, path: Self::Path<'fs>) -> Result<(), ReffFsError> {
-        let (parent, name) = path.parent().ok_or(FileSystemError::OperateOnRoot)?;
+        let (parent, name) = path.clone().parent().ok_or(FileSystemError::OperateOnRoot)?;
+        if self.traverse_dir_mut(parent.clone())?.contains_key(&name.to_string()) {
+            Err(FileSystemError::AlreadyExists(path))?
+        }
         let new_dir = self.fresh(Node::with_inner(NodeInner::Dir(HashMap::new())));
         let dir = self.traverse_dir_mut(parent)?;
         dir.insert(name.to_string(), new_dir);
         Ok(())
     }
 
-    fn read_dir(&self, path: Self::Path<'fs>) -> Result<Vec<FileName>, ReffFsError> {
-        let node = self.traverse(path.clone())?;
-        let children = node.dir(path.clone())?;
-        children.into_iter().map(|(name, _)| FileName::new(name)).collect()
-    }
-
     fn create_link(&mut self, path: Self::Path<'fs>, target: Self::Path<'fs>) -> Result<(), ReffFsError> {
-        let target = self.traverse_id(target)?;
+        let target_path = target.clone();
+        let target = self.traverse_id_nofollow(target)?;
+        if self[target].is_dir() {
+            Err(FileSystemError::OperateFileOnDir(target_path))?
+        }
         let (parent, name) = path.clone().parent().ok_or(FileSystemError::OperateOnRoot)?;
         let parent = self.traverse_dir_mut(parent)?;
+        if parent.contains_key(&name.to_string()) {
+            Err(FileSystemError::AlreadyExists(path))?
+        }
         parent.insert(name.to_string(), target);
+        self[target].cnt += 1;
+        Ok(())
+    }
+
+    fn create_symlink(&mut self, path: Self::Path<'fs>, target: String) -> Result<(), ReffFsError> {
+        let (parent, name) = path.clone().parent().ok_or(FileSystemError::OperateOnRoot)?;
+        if self.traverse_dir_mut(parent.clone())?.contains_key(&name.to_string()) {
+            Err(FileSystemError::AlreadyExists(path))?
+        }
+        let new_link = self.fresh(Node::with_inner(NodeInner::Symlink(target)));
+        let dir = self.traverse_dir_mut(parent)?;
+        dir.insert(name.to_string(), new_link);
+        Ok(())
+    }
+
+    fn rename(&mut self, from: Self::Path<'fs>, to: Self::Path<'fs>) -> Result<(), ReffFsError> {
+        let (from_parent, from_name) = from.clone().parent().ok_or(FileSystemError::OperateOnRoot)?;
+        let (to_parent, to_name) = to.clone().parent().ok_or(FileSystemError::OperateOnRoot)?;
+        let src = self.traverse_id(from_parent)?;
+        let node = *self[src]
+            .dir(from.clone())?
+            .get(&from_name.to_string())
+            .ok_or(FileSystemError::FileNotInDir(from.clone()))?;
+        let ancestors = self.resolve(to_parent, true)?;
+        let dst = *ancestors.last().expect("the root is never popped");
+        if self[node].is_dir() && ancestors.contains(&node) {
+            Err(FileSystemError::MoveIntoSubtree(to.clone()))?
+        }
+        if let Some(&existing) = self[dst].dir(to.clone())?.get(&to_name.to_string()) {
+            if existing == node {
+                // both names already lead to the same node
+                return Ok(());
+            }
+            match (self[node].is_dir(), &self[existing].inner) {
+                (true, NodeInner::Dir(children)) if !children.is_empty() => {
+                    Err(FileSystemError::RemoveNonEmptyDir(to.clone()))?
+                }
+                (true, NodeInner::Dir(_)) => {}
+                (true, _) => Err(FileSystemError::OperateDirOnFile(to.clone()))?,
+                (false, NodeInner::Dir(_)) => Err(FileSystemError::OperateFileOnDir(to.clone()))?,
+                (false, _) => {}
+            }
+            self.unlink(existing);
+        }
+        self[src].dir_mut(from)?.remove(&from_name.to_string());
+        self[dst].dir_mut(to)?.insert(to_name.to_string(), node);
         Ok(())
     }
 
@@ -295,6 +478,7 @@ impl<'fs> IFileSystem<'fs> for ReffFs {
             }
         }
         self[dir].dir_mut(path.clone())?.remove(&name.to_string());
+        self.unlink(node);
         Ok(())
     }
 }
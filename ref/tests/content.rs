@@ -0,0 +1,16 @@
+use refffs::{Data, FileSystemError, FsPath, IFileSystem, IReadFileSystem, ReffFs};
+
+fn path(s: &str) -> FsPath {
+    FsPath::try_from(s).unwrap()
+}
+
+#[test]
+fn write_at_past_the_largest_offset_fails() {
+    let mut fs = ReffFs::init();
+    fs.create_file(path("/f")).unwrap();
+    fs.write_file(path("/f"), Data::new(b"abc")).unwrap();
+
+    let err = fs.write_at(path("/f"), usize::MAX, Data::new(b"x")).unwrap_err();
+    assert!(matches!(err, FileSystemError::FileTooLarge(_)));
+    assert_eq!(fs.read_file(path("/f")).unwrap().as_ref(), b"abc");
+}
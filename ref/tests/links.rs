@@ -0,0 +1,59 @@
+use refffs::{Data, FileSystemError, FsPath, IFileSystem, IMeta, IReadFileSystem, ReffFs};
+
+fn path(s: &str) -> FsPath {
+    FsPath::try_from(s).unwrap()
+}
+
+fn nlink(fs: &ReffFs, s: &str) -> usize {
+    fs.metadata(path(s)).unwrap().nlink()
+}
+
+#[test]
+fn link_counts_follow_links_and_removes() {
+    let mut fs = ReffFs::init();
+    fs.create_file(path("/a")).unwrap();
+    fs.write_file(path("/a"), Data::new(b"shared")).unwrap();
+    fs.create_dir(path("/d")).unwrap();
+    fs.create_link(path("/b"), path("/a")).unwrap();
+    fs.create_link(path("/d/c"), path("/b")).unwrap();
+    assert_eq!(nlink(&fs, "/a"), 3);
+    assert_eq!(fs.read_file(path("/d/c")).unwrap().as_ref(), b"shared");
+
+    fs.remove(path("/a")).unwrap();
+    assert_eq!(nlink(&fs, "/b"), 2);
+    fs.remove(path("/d/c")).unwrap();
+    assert_eq!(nlink(&fs, "/b"), 1);
+    assert!(fs.free.is_empty());
+    fs.remove(path("/b")).unwrap();
+    assert_eq!(fs.free.len(), 1);
+}
+
+#[test]
+fn creating_over_a_taken_name_fails() {
+    let mut fs = ReffFs::init();
+    fs.create_file(path("/a")).unwrap();
+    fs.create_link(path("/b"), path("/a")).unwrap();
+    fs.create_dir(path("/d")).unwrap();
+    fs.create_file(path("/d/f")).unwrap();
+
+    let taken = [
+        fs.create_file(path("/b")),
+        fs.create_dir(path("/d")),
+        fs.create_link(path("/a"), path("/a")),
+        fs.create_link(path("/d"), path("/a")),
+        fs.create_symlink(path("/b"), "a".to_owned()),
+    ];
+    for result in taken {
+        assert!(matches!(result, Err(FileSystemError::AlreadyExists(_))), "{:?}", result);
+    }
+    assert_eq!(nlink(&fs, "/a"), 2);
+    assert_eq!(fs.read_dir(path("/d")).unwrap().len(), 1);
+    let nodes = fs.nodes.len();
+    fs.remove(path("/a")).unwrap();
+    fs.remove(path("/b")).unwrap();
+    assert_eq!(fs.free.len(), 1);
+    // the freed node is handed out again
+    fs.create_file(path("/c")).unwrap();
+    assert!(fs.free.is_empty());
+    assert_eq!(fs.nodes.len(), nodes);
+}
@@ -0,0 +1,289 @@
+use interface::{FileSystemError, IFileSystem, IMeta, IPath};
+use std::{
+    fmt::Display,
+    io::{BufRead, Write},
+};
+
+pub const HELP: &str = "\
+commands:
+    cd [path]              change the working directory, `/` without a path
+    pwd                    print the working directory
+    ls [path]              list a directory
+    cat <path>             print a file
+    echo <text> > <path>   write text to a file, creating it if needed
+    echo <text> >> <path>  append text to a file, creating it if needed
+    truncate <path> <len>  cut a file down, or extend it with zeros, to len bytes
+    mkdir <path>           create a directory
+    rm <path>              remove a file or an empty directory
+    mv <from> <to>         rename or move an entry, replacing a file at to
+    ln <target> <path>     create a hard link at path to target
+    ln -s <target> <path>  create a symbolic link at path to target
+    stat <path>            show metadata
+    tree [path]            print a directory tree
+    history                list previous commands
+    !<n>                   run command number n from the history again
+    help                   show this message
+    exit                   leave the shell
+
+paths may be absolute or relative to the working directory, and may use `.` and `..`";
+
+#[derive(Debug)]
+pub struct ShellError(pub String);
+
+impl Display for ShellError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl<P: Display> From<FileSystemError<P>> for ShellError {
+    fn from(err: FileSystemError<P>) -> Self {
+        ShellError(err.to_string())
+    }
+}
+
+impl From<std::io::Error> for ShellError {
+    fn from(err: std::io::Error) -> Self {
+        ShellError(err.to_string())
+    }
+}
+
+type ShellResult<T> = Result<T, ShellError>;
+
+/// What the shell should do after a command.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Flow {
+    Continue,
+    Exit,
+}
+
+/// A command interpreter over any file system, with a working directory and
+/// a command history.
+pub struct Shell<F> {
+    pub fs: F,
+    cwd: Vec<String>,
+    history: Vec<String>,
+}
+
+impl<'fs, F> Shell<F>
+where
+    F: IFileSystem<'fs>,
+    <F::Path<'fs> as IPath<'fs>>::Raw: From<String> + Display,
+    <F::Path<'fs> as IPath<'fs>>::Segment: Display,
+    F::Data: From<Vec<u8>> + AsRef<[u8]>,
+{
+    pub fn new(fs: F) -> Self {
+        Self {
+            fs,
+            cwd: vec![],
+            history: vec![],
+        }
+    }
+    pub fn cwd(&self) -> String {
+        format!("/{}", self.cwd.join("/"))
+    }
+    /// Resolve `raw` against the working directory into its absolute segments.
+    fn resolve(&self, raw: &str) -> Vec<String> {
+        let mut segments = if raw.starts_with('/') { vec![] } else { self.cwd.clone() };
+        for segment in raw.split('/') {
+            match segment {
+                "" | "." => {}
+                ".." => {
+                    segments.pop();
+                }
+                segment => segments.push(segment.to_owned()),
+            }
+        }
+        segments
+    }
+    fn path(&self, raw: &str) -> ShellResult<F::Path<'fs>> {
+        let absolute = format!("/{}", self.resolve(raw).join("/"));
+        Ok(F::Path::try_from(absolute.into())?)
+    }
+    fn arg<'a>(args: &[&'a str], idx: usize, what: &str) -> ShellResult<&'a str> {
+        args.get(idx)
+            .copied()
+            .ok_or_else(|| ShellError(format!("missing {}", what)))
+    }
+    fn names(&self, path: F::Path<'fs>) -> ShellResult<Vec<String>> {
+        let mut names = self
+            .fs
+            .read_dir(path)?
+            .into_iter()
+            .map(|name| name.to_string())
+            .collect::<Vec<_>>();
+        names.sort();
+        Ok(names)
+    }
+    /// How `ls` and `tree` show the entry `raw`: a trailing `/` for a directory
+    /// and the target for a symbolic link. Also tells whether it is a directory.
+    fn label(&self, raw: &str, name: &str) -> ShellResult<(String, bool)> {
+        let meta = self.fs.metadata(self.path(raw)?)?;
+        if meta.is_symlink() {
+            let target = self.fs.read_link(self.path(raw)?)?;
+            Ok((format!("{} -> {}", name, target), false))
+        } else if meta.is_dir() {
+            Ok((format!("{}/", name), true))
+        } else {
+            Ok((name.to_owned(), false))
+        }
+    }
+    fn tree(&self, raw: &str, depth: usize, out: &mut dyn Write) -> ShellResult<()> {
+        for name in self.names(self.path(raw)?)? {
+            let child = format!("{}/{}", raw.trim_end_matches('/'), name);
+            let (label, is_dir) = self.label(&child, &name)?;
+            writeln!(out, "{}{}", "    ".repeat(depth), label)?;
+            if is_dir {
+                self.tree(&child, depth + 1, out)?;
+            }
+        }
+        Ok(())
+    }
+    /// Run a single command line, writing its output to `out`.
+    pub fn execute(&mut self, line: &str, out: &mut dyn Write) -> ShellResult<Flow> {
+        let line = line.trim();
+        if line.is_empty() {
+            return Ok(Flow::Continue);
+        }
+        if let Some(idx) = line.strip_prefix('!') {
+            let entry = idx
+                .parse::<usize>()
+                .ok()
+                .and_then(|idx| self.history.get(idx.wrapping_sub(1)))
+                .cloned()
+                .ok_or_else(|| ShellError(format!("no history entry `{}`", idx)))?;
+            writeln!(out, "{}", entry)?;
+            return self.execute(&entry, out);
+        }
+        self.history.push(line.to_owned());
+        let (command, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let args = rest.split_whitespace().collect::<Vec<_>>();
+        match command {
+            "cd" => {
+                let raw = args.first().copied().unwrap_or("/");
+                // read_dir follows symbolic links, metadata does not
+                if self.fs.read_dir(self.path(raw)?).is_err() {
+                    Err(ShellError(format!("not a directory: `{}`", raw)))?
+                }
+                self.cwd = self.resolve(raw);
+            }
+            "pwd" => writeln!(out, "{}", self.cwd())?,
+            "ls" => {
+                let raw = args.first().copied().unwrap_or(".");
+                for name in self.names(self.path(raw)?)? {
+                    let (label, _) = self.label(&format!("{}/{}", raw, name), &name)?;
+                    writeln!(out, "{}", label)?;
+                }
+            }
+            "cat" => {
+                let data = self.fs.read_file(self.path(Self::arg(&args, 0, "path")?)?)?;
+                out.write_all(data.as_ref())?;
+                if !data.as_ref().ends_with(b"\n") {
+                    writeln!(out)?;
+                }
+            }
+            "echo" => match rest.rsplit_once('>') {
+                None => writeln!(out, "{}", rest.trim())?,
+                Some((text, target)) => {
+                    let (text, append) = match text.strip_suffix('>') {
+                        Some(text) => (text, true),
+                        None => (text, false),
+                    };
+                    let text = text.trim();
+                    let text = text
+                        .strip_prefix('"')
+                        .and_then(|text| text.strip_suffix('"'))
+                        .unwrap_or(text);
+                    let path = self.path(target.trim())?;
+                    if self.fs.metadata(self.path(target.trim())?).is_err() {
+                        self.fs.create_file(self.path(target.trim())?)?;
+                    }
+                    let data = format!("{}\n", text).into_bytes().into();
+                    if append {
+                        self.fs.append(path, data)?;
+                    } else {
+                        self.fs.write_file(path, data)?;
+                    }
+                }
+            },
+            "truncate" => {
+                let path = self.path(Self::arg(&args, 0, "path")?)?;
+                let len = Self::arg(&args, 1, "length")?;
+                let len = len
+                    .parse()
+                    .map_err(|_| ShellError(format!("invalid length `{}`", len)))?;
+                self.fs.truncate(path, len)?
+            }
+            "mkdir" => self.fs.create_dir(self.path(Self::arg(&args, 0, "path")?)?)?,
+            "rm" => self.fs.remove(self.path(Self::arg(&args, 0, "path")?)?)?,
+            "mv" => {
+                let from = self.path(Self::arg(&args, 0, "source path")?)?;
+                let to = self.path(Self::arg(&args, 1, "destination path")?)?;
+                self.fs.rename(from, to)?
+            }
+            "ln" if args.first() == Some(&"-s") => {
+                // the target is stored as typed, not resolved against the working directory
+                let target = Self::arg(&args, 1, "target")?.to_owned();
+                let path = self.path(Self::arg(&args, 2, "link path")?)?;
+                self.fs.create_symlink(path, target.into())?
+            }
+            "ln" => {
+                let target = self.path(Self::arg(&args, 0, "target")?)?;
+                let path = self.path(Self::arg(&args, 1, "link path")?)?;
+                self.fs.create_link(path, target)?
+            }
+            "stat" => {
+                let raw = Self::arg(&args, 0, "path")?;
+                let meta = self.fs.metadata(self.path(raw)?)?;
+                writeln!(out, "path: /{}", self.resolve(raw).join("/"))?;
+                if meta.is_symlink() {
+                    writeln!(out, "type: symbolic link")?;
+                    writeln!(out, "links: {}", meta.nlink())?;
+                    writeln!(out, "target: {}", self.fs.read_link(self.path(raw)?)?)?;
+                } else if meta.is_dir() {
+                    writeln!(out, "type: directory")?;
+                    writeln!(out, "items: {}", self.names(self.path(raw)?)?.len())?;
+                } else {
+                    writeln!(out, "type: file")?;
+                    writeln!(out, "links: {}", meta.nlink())?;
+                    writeln!(out, "size: {}", self.fs.read_file(self.path(raw)?)?.as_ref().len())?;
+                }
+            }
+            "tree" => {
+                let raw = args.first().copied().unwrap_or(".");
+                writeln!(out, "/{}", self.resolve(raw).join("/"))?;
+                self.tree(raw, 1, out)?;
+            }
+            "history" => {
+                for (idx, entry) in self.history.iter().enumerate() {
+                    writeln!(out, "{:>4}  {}", idx + 1, entry)?;
+                }
+            }
+            "help" => writeln!(out, "{}", HELP)?,
+            "exit" | "quit" => return Ok(Flow::Exit),
+            _ => Err(ShellError(format!("unknown command `{}`, try `help`", command)))?,
+        }
+        Ok(Flow::Continue)
+    }
+    /// Read commands from `input` until it ends or `exit` is given. Errors are
+    /// reported on `out` and do not stop the shell. A prompt is shown when
+    /// `interactive` is set.
+    pub fn run(&mut self, input: impl BufRead, out: &mut dyn Write, interactive: bool) -> std::io::Result<()> {
+        let mut lines = input.lines();
+        loop {
+            if interactive {
+                write!(out, "{} $ ", self.cwd())?;
+                out.flush()?;
+            }
+            let Some(line) = lines.next() else {
+                break;
+            };
+            match self.execute(&line?, out) {
+                Ok(Flow::Continue) => {}
+                Ok(Flow::Exit) => break,
+                Err(err) => writeln!(out, "error: {}", err)?,
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,43 @@
+use cowffs::FileSys;
+use interface::IFileSystem;
+use refffs::ReffFs;
+use shell::Shell;
+use std::io::IsTerminal;
+
+const USAGE: &str = "\
+usage: ffsh [ref | cow [image]]
+
+Starts a shell over an in-memory reference file system (`ref`, the default)
+or a copy-on-write one (`cow`). With an image, the copy-on-write file system
+is loaded from it, or created if it does not exist, and written back on exit.";
+
+fn main() -> anyhow::Result<()> {
+    let mut args = std::env::args().skip(1);
+    let interactive = std::io::stdin().is_terminal();
+    let stdin = std::io::stdin().lock();
+    let mut stdout = std::io::stdout();
+    match args.next().as_deref() {
+        None | Some("ref") => {
+            Shell::new(ReffFs::init()).run(stdin, &mut stdout, interactive)?;
+        }
+        Some("cow") => match args.next() {
+            None => Shell::new(FileSys::init()).run(stdin, &mut stdout, interactive)?,
+            Some(image) => {
+                let image = std::path::PathBuf::from(image);
+                let fs = if image.exists() {
+                    FileSys::fs_disk_load(image)?
+                } else {
+                    FileSys::fs_disk_init(image)?
+                };
+                let mut shell = Shell::new(fs);
+                shell.run(stdin, &mut stdout, interactive)?;
+                shell.fs.fs_disk_dump()?;
+            }
+        },
+        Some(_) => {
+            eprintln!("{}", USAGE);
+            std::process::exit(2);
+        }
+    }
+    Ok(())
+}
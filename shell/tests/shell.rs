@@ -0,0 +1,121 @@
+use cowffs::FileSys;
+use interface::{IFileSystem, IPath};
+use refffs::ReffFs;
+use shell::{Flow, Shell};
+use std::fmt::Display;
+
+/// Run `script` through a shell over a fresh `F` and return what it printed.
+fn run<'fs, F>(script: &str) -> String
+where
+    F: IFileSystem<'fs>,
+    <F::Path<'fs> as IPath<'fs>>::Raw: From<String> + Display,
+    <F::Path<'fs> as IPath<'fs>>::Segment: Display,
+    F::Data: From<Vec<u8>> + AsRef<[u8]>,
+{
+    let mut out = vec![];
+    Shell::new(F::init()).run(script.as_bytes(), &mut out, false).unwrap();
+    String::from_utf8(out).unwrap()
+}
+
+/// Run `script` over both file systems, which must print the same.
+fn run_both(script: &str) -> String {
+    let reference = run::<ReffFs>(script);
+    assert_eq!(run::<FileSys>(script), reference);
+    reference
+}
+
+#[test]
+fn paths_resolve_against_the_working_directory() {
+    let out = run_both(
+        "mkdir /a
+         cd a
+         mkdir b
+         cd ./b/../b
+         pwd
+         echo hello > f
+         echo world >> ./f
+         cd ..
+         cat b/f
+         cat /a/b/f
+         cd
+         pwd
+         ls a",
+    );
+    assert_eq!(out, "/a/b\nhello\nworld\nhello\nworld\n/\nb/\n");
+}
+
+#[test]
+fn links_show_in_ls_tree_and_stat() {
+    let out = run_both(
+        "mkdir /d
+         echo content > /d/f
+         ln /d/f /hard
+         ln -s d/f /soft
+         ls
+         tree
+         stat /hard
+         stat /soft
+         cat /soft
+         cd /soft
+         truncate /hard 4
+         cat /d/f",
+    );
+    assert_eq!(
+        out,
+        "d/\nhard\nsoft -> d/f\n\
+         /\n    d/\n        f\n    hard\n    soft -> d/f\n\
+         path: /hard\ntype: file\nlinks: 2\nsize: 8\n\
+         path: /soft\ntype: symbolic link\nlinks: 1\ntarget: d/f\n\
+         content\n\
+         error: not a directory: `/soft`\n\
+         cont\n"
+    );
+}
+
+#[test]
+fn errors_do_not_stop_the_shell() {
+    let out = run_both(
+        "cat /missing
+         rm /
+         mkdir /d
+         mkdir /d
+         bogus
+         cd /missing
+         ln -s
+         pwd",
+    );
+    let lines = out.lines().collect::<Vec<_>>();
+    assert_eq!(lines.len(), 7);
+    assert!(lines[..6].iter().all(|line| line.starts_with("error: ")), "{}", out);
+    assert_eq!(lines[6], "/");
+}
+
+#[test]
+fn history_reruns_commands() {
+    let out = run_both(
+        "mkdir /d
+         cd /d
+         echo one >> f
+         !3
+         history
+         !9
+         cat f",
+    );
+    assert_eq!(
+        out,
+        "echo one >> f\n\
+         \x20  1  mkdir /d\n   2  cd /d\n   3  echo one >> f\n   4  echo one >> f\n   5  history\n\
+         error: no history entry `9`\n\
+         one\none\n"
+    );
+}
+
+#[test]
+fn exit_ends_the_script() {
+    assert_eq!(run_both("pwd\nexit\npwd"), "/\n");
+    let mut shell = Shell::new(ReffFs::init());
+    let mut out = vec![];
+    assert_eq!(shell.execute("quit", &mut out).unwrap(), Flow::Exit);
+    assert_eq!(shell.execute("  ", &mut out).unwrap(), Flow::Continue);
+    assert!(out.is_empty());
+}